@@ -1,93 +1,317 @@
-use crate::tshark;
+use crate::csv_output::{self, CsvOptions};
+use crate::error::{Error, Result};
+use crate::html_output;
+use crate::json_output;
+use crate::location::{self, GeoLocation, GeoSource};
+use crate::packet::{self, Backend};
 use crate::stats;
-use crate::location;
-use crate::csv_output;
-
-use std::{fs, path::{Path},io::{BufReader, BufRead}, fs::File};
-use std::result::Result;
-use indicatif::{ProgressBar, ProgressStyle};
-use std::collections::HashMap;
-use crate::stats::FlowStat;
+use crate::xlsx_output;
+
+use crate::dns::ResolutionTable;
+use crate::filter::Filter;
+use crate::flows::FlowTable;
+use crate::stats::{Aggregator, FlowStat, LocalHost, NetConfig};
+use clap::ValueEnum;
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
 use std::path::PathBuf;
+use std::{fs, path::Path};
+
+/// 单个文件（或合并后多个文件）的统计结果
+#[derive(Debug, Default)]
+pub struct AggregateResult {
+    /// 各远端 IP 统计
+    pub stats: HashMap<IpAddr, FlowStat>,
+    pub total: u64,
+    pub up: u64,
+    pub down: u64,
+    /// 本机地址，合并多个文件时为各文件本机地址的并集
+    pub local: LocalHost,
+    /// 五元组会话表，仅在启用 --flows 时统计
+    pub flows: Option<FlowTable>,
+    /// 参与统计的各抓包文件的摘要，合并多个文件时按路径排序
+    pub files: Vec<FileSummary>,
+    /// DNS 应答建立的解析表，合并多个文件时一并合并，使前一个文件中的解析也能用于后一个文件
+    pub dns: ResolutionTable,
+}
 
-pub fn parse_and_aggregate(
-    input_pcap: &str,
-    tshark_tsv: &str,
-    company: Option<&str>,
-) -> Result<(HashMap<String, FlowStat>, u64, u64, u64, String), Box<dyn std::error::Error>> {
-    tshark::run_tshark(input_pcap, tshark_tsv)?;
-
-    let file = File::open(tshark_tsv)?;
-    let reader = BufReader::new(file);
-    let lines: Vec<String> = reader.lines().filter_map(Result::ok).collect();
-
-    let local_ip = match stats::find_local_ip(&lines) {
-        Ok(ip) => {
-            println!("⏳ 定位到局域网IP: {}", ip);
-            ip
+/// 单个抓包文件的统计摘要
+#[derive(Debug, Clone)]
+pub struct FileSummary {
+    pub path: PathBuf,
+    /// 该文件中的本机地址，多主机模式下为该主机
+    pub local: LocalHost,
+    /// 远端 IP 数（应用最小数据量和过滤规则之前）
+    pub remote_ips: usize,
+    pub total: u64,
+    pub up: u64,
+    pub down: u64,
+    /// 首个、最后一个数据包的时间（Unix 秒），没有时间戳时为 0
+    pub first_seen: f64,
+    pub last_seen: f64,
+}
+
+impl FileSummary {
+    fn new(
+        path: &str,
+        stats: &HashMap<IpAddr, FlowStat>,
+        totals: (u64, u64, u64),
+        local: &LocalHost,
+    ) -> Self {
+        let (first_seen, last_seen) = stats::capture_period(stats).unwrap_or_default();
+        FileSummary {
+            path: PathBuf::from(path),
+            local: local.clone(),
+            remote_ips: stats.len(),
+            total: totals.0,
+            up: totals.1,
+            down: totals.2,
+            first_seen,
+            last_seen,
         }
-        Err(e) => {
-            eprintln!("❌ 错误: {}", e);
-            std::process::exit(1);
+    }
+}
+
+impl AggregateResult {
+    // 结束聚合并生成单个文件的统计结果，尚未标注解析域名
+    fn from_aggregator(path: &str, agg: Aggregator) -> Self {
+        let local = agg.local().clone();
+        let (stats, total, up, down, dns, flows) = agg.finish();
+        let files = vec![FileSummary::new(path, &stats, (total, up, down), &local)];
+        AggregateResult {
+            stats,
+            total,
+            up,
+            down,
+            local,
+            flows,
+            files,
+            dns,
         }
-    };
+    }
+
+    /// 按解析表标注各远端 IP 的解析域名与 CNAME 链，合并完所有文件后调用
+    pub fn attribute_names(&mut self) {
+        stats::attribute_names(&mut self.stats, &self.dns);
+    }
+
+    /// 合并两份统计结果，把较小的表并入较大的表
+    fn merge(self, other: AggregateResult) -> AggregateResult {
+        let (mut big, small) = if self.stats.len() >= other.stats.len() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        for (ip, stat) in small.stats {
+            big.stats.entry(ip).or_default().merge(stat);
+        }
+        big.total += small.total;
+        big.files.extend(small.files);
+        big.files.sort_by(|a, b| a.path.cmp(&b.path));
+        big.up += small.up;
+        big.down += small.down;
+        for net in small.local.nets {
+            if !big.local.nets.contains(&net) {
+                big.local.nets.push(net);
+            }
+        }
+        big.flows = match (big.flows, small.flows) {
+            (Some(mut a), Some(b)) => {
+                a.merge(b);
+                Some(a)
+            }
+            (a, b) => a.or(b),
+        };
+        big.dns.merge(small.dns);
+        big
+    }
+}
 
-    let (stats_map, total, up, down) = stats::aggregate_with_local_ip(&lines, &local_ip,company);
-    Ok((stats_map, total, up, down, local_ip))
+/// 输出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum OutputFormat {
+    /// CSV 表格
+    #[default]
+    Csv,
+    /// JSON 文档：元数据、总计与各 IP 条目，数据量为字节数
+    Json,
+    /// JSON Lines：每行一个远端 IP
+    Jsonl,
+    /// Excel 工作簿：概览、按 IP、按域名，合并时另有按文件工作表
+    Xlsx,
+    /// HTML 报告：单个文件，内嵌图表与可搜索的表格，可离线打开
+    Html,
 }
 
+impl OutputFormat {
+    /// 主报告文件的扩展名
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Json => "json",
+            OutputFormat::Jsonl => "jsonl",
+            OutputFormat::Xlsx => "xlsx",
+            OutputFormat::Html => "html",
+        }
+    }
+}
 
-// 分析单个文件
-pub fn analyze_single_file(input_pcap: &str, api_url: &str, tshark_tsv:&str,company: Option<&str>,) -> Result<(), Box<dyn std::error::Error>> {
-    // 输出文件名
-    let output_csv = {
-        let path = Path::new(input_pcap);
-        let stem = path.file_stem().unwrap_or_default();
-        let parent = path.parent().unwrap_or_else(|| Path::new("."));
-        let mut output_path = parent.join(stem);
-        output_path.set_extension("csv");
-        output_path.to_string_lossy().to_string()
-    };
+/// 分析选项，由命令行参数构造
+#[derive(Debug, Clone)]
+pub struct AnalyzeOptions {
+    /// 输出文件路径，为 None 时按输入路径生成；逐个分析目录时为输出目录
+    pub output: Option<PathBuf>,
+    pub format: OutputFormat,
+    /// 总数据量低于该字节数的 IP 不输出
+    pub min_bytes: u64,
+    /// 结果过滤规则（-c 包含、--exclude 排除），在查询归属地之后应用
+    pub filter: Filter,
+    pub backend: Backend,
+    /// 时间序列的统计间隔（秒），指定时额外输出 <文件名>_timeseries.csv
+    pub interval: Option<u64>,
+    /// 指定的本机地址（IP、网段或 MAC），为 None 时自动定位
+    pub local: Option<LocalHost>,
+    /// 按内网主机分别统计，每个主机输出一份报告
+    pub multi_host: bool,
+    /// 内网网段与排除地址，命令行程序由 [`NetConfig::from_env`] 读取
+    pub net: NetConfig,
+    pub csv: CsvOptions,
+}
 
+/// 多主机模式的统计结果，键为内网主机地址
+pub type HostResults = HashMap<IpAddr, AggregateResult>;
 
-    tshark::run_tshark(input_pcap, tshark_tsv)?;
+/// 解析并聚合单个抓包文件。返回的结果尚未标注解析域名，合并完所有文件后调用
+/// [`AggregateResult::attribute_names`]
+pub fn parse_and_aggregate(input_pcap: &str, options: &AnalyzeOptions) -> Result<AggregateResult> {
+    let backend = options.backend;
 
+    // 指定了本机地址时无需定位，一遍读完
+    if let Some(local) = &options.local {
+        let mut agg = Aggregator::new(local, &options.net, options.interval, options.csv.flows);
+        packet::for_each_packet(input_pcap, backend, &mut |record| agg.push(&record))?;
+        return Ok(AggregateResult::from_aggregator(input_pcap, agg));
+    }
 
-    let file = File::open(tshark_tsv)?;
-    let reader = BufReader::new(file);
-    let lines: Vec<String> = reader.lines().filter_map(Result::ok).collect();
+    // 未指定本机地址时边定位边按各候选地址聚合，读完后取定位结果对应的统计
+    let mut agg = stats::AutoAggregator::new(&options.net, options.interval, options.csv.flows);
+    packet::for_each_packet(input_pcap, backend, &mut |record| agg.push(&record))?;
+    let agg = agg.finish().map_err(Error::LocalHostNotFound)?;
+    Ok(AggregateResult::from_aggregator(input_pcap, agg))
+}
 
-    let local_ip = match stats::find_local_ip(&lines) {
-        Ok(ip) => {
-            println!("⏳ 定位到局域网IP: {}", ip);
-            ip
-        }
-        Err(e) => {
-            eprintln!("❌ 错误: {}", e);
-            std::process::exit(1);
+/// 多主机模式：抓包中的每个内网主机（或 --local 指定范围内的每个地址）各自作为本机统计，
+/// 与 [`parse_and_aggregate`] 一样尚未标注解析域名
+pub fn parse_multi_host(input_pcap: &str, options: &AnalyzeOptions) -> Result<HostResults> {
+    let mut hosts: HashMap<IpAddr, Aggregator> = HashMap::new();
+
+    packet::for_each_packet(input_pcap, options.backend, &mut |record| {
+        let ends = [(record.src, record.src_mac), (record.dst, record.dst_mac)];
+        for (i, (ip, mac)) in ends.into_iter().enumerate() {
+            let Some(ip) = ip else {
+                continue;
+            };
+            // 源和目的是同一地址时只统计一次
+            if i == 1 && record.src == Some(ip) {
+                continue;
+            }
+            if !stats::is_internal_host(&ip, mac, options.local.as_ref(), &options.net) {
+                continue;
+            }
+            hosts
+                .entry(ip)
+                .or_insert_with(|| {
+                    Aggregator::new(
+                        &LocalHost::from(ip),
+                        &options.net,
+                        options.interval,
+                        options.csv.flows,
+                    )
+                })
+                .push(&record);
         }
-    };
+    })?;
+
+    // 只保留有外部流量的主机
+    let results: HostResults = hosts
+        .into_iter()
+        .map(|(ip, agg)| (ip, AggregateResult::from_aggregator(input_pcap, agg)))
+        .filter(|(_, result)| !result.stats.is_empty())
+        .collect();
+
+    if results.is_empty() {
+        return Err(Error::NoInternalHosts);
+    }
+    Ok(results)
+}
 
-    let (stats_map, total, up, down) = stats::aggregate_with_local_ip(&lines, &local_ip,company);
+// 合并两份多主机统计结果
+fn merge_host_results(mut a: HostResults, b: HostResults) -> HostResults {
+    for (ip, result) in b {
+        let merged = match a.remove(&ip) {
+            Some(existing) => existing.merge(result),
+            None => result,
+        };
+        a.insert(ip, merged);
+    }
+    a
+}
 
-    if !api_url.is_empty() {
-        let ip_list: Vec<String> = stats_map.keys().cloned().collect();
-        let locations = location::query_ip_locations(&ip_list, 100, api_url);
+/// 一份输出报告：单个文件、合并后的目录，或多主机模式下的一个内网主机
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// 主报告文件路径，附加报表与其同名（如 <文件名>_ports.csv）
+    pub output: PathBuf,
+    /// 本机地址，多主机模式下为该主机
+    pub local: LocalHost,
+    /// 报告中的远端 IP 数
+    pub rows: usize,
+    pub total: u64,
+    pub up: u64,
+    pub down: u64,
+}
 
-        csv_output::write_csv(&output_csv, &stats_map, &locations, total, up, down)?;
+// 分析单个文件，返回写出的报告（多主机模式下每个内网主机一份）
+pub fn analyze_single_file(
+    input_pcap: &str,
+    geo: &GeoSource,
+    options: &AnalyzeOptions,
+) -> Result<Vec<Report>> {
+    // 输出文件名，未指定时为输入文件同目录下的同名文件，扩展名随输出格式
+    let output_csv = match &options.output {
+        Some(output) => create_output_dir(output)?,
+        None => {
+            let path = Path::new(input_pcap);
+            let stem = path.file_stem().unwrap_or_default();
+            let parent = path.parent().unwrap_or_else(|| Path::new("."));
+            let mut output_path = parent.join(stem);
+            output_path.set_extension(options.format.extension());
+            output_path.to_string_lossy().to_string()
+        }
+    };
 
-        println!("✅ 分析完成，结果已保存到 {}", output_csv);
+    if options.multi_host {
+        let results = parse_multi_host(input_pcap, options)?;
+        return write_host_outputs(&output_csv, results, geo, options);
     }
-    
-    Ok(())
+
+    let mut result = parse_and_aggregate(input_pcap, options)?;
+    result.attribute_names();
+    let locations = enrich(geo, vec![&mut result.stats], options);
+    Ok(vec![write_report(
+        &output_csv,
+        &result,
+        locations.as_ref(),
+        options,
+    )?])
 }
 
-// 分析目录中所有 pcap 和 pcapng 文件
-pub fn analyze_directory(dir_path: &str, api_url: &str, tshark_tsv:&str,company: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+/// 列出目录中的 .pcap 与 .pcapng 文件，目录中没有抓包文件时返回错误
+pub fn find_capture_files(dir_path: &str) -> Result<Vec<PathBuf>> {
     let path = Path::new(dir_path);
     if !path.is_dir() {
-        eprintln!("❌ {} 不是一个目录", dir_path);
-        std::process::exit(1);
+        return Err(Error::NotADirectory(path.to_path_buf()));
     }
 
     // 收集所有符合条件的文件
@@ -107,139 +331,287 @@ pub fn analyze_directory(dir_path: &str, api_url: &str, tshark_tsv:&str,company:
         .collect();
 
     if files.is_empty() {
-        eprintln!("⚠️ 目录 {} 中没有找到 .pcap 或 .pcapng 文件", dir_path);
-        std::process::exit(0);
+        return Err(Error::NoCaptureFiles(path.to_path_buf()));
     }
+    Ok(files)
+}
 
-    let pb = ProgressBar::new(files.len() as u64);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} {msg}")
-            .unwrap()
-            .progress_chars("#>-"),
-    );
-
-    for file_path in files {
-        pb.set_message(format!("分析文件: {}", file_path.display()));
-        analyze_single_file(file_path.to_str().unwrap(), api_url, tshark_tsv,company)?;
-        pb.inc(1);
+// 分析目录中所有 pcap 和 pcapng 文件，每个文件单独输出；每分析完一个文件回调一次 on_file_done
+pub fn analyze_directory(
+    dir_path: &str,
+    geo: &GeoSource,
+    options: &AnalyzeOptions,
+    on_file_done: &(dyn Fn(&Path) + Sync),
+) -> Result<Vec<Report>> {
+    let files = find_capture_files(dir_path)?;
+
+    // 指定了输出目录时，各文件的结果写到该目录下
+    if let Some(output_dir) = &options.output {
+        fs::create_dir_all(output_dir)?;
     }
 
-    pb.finish_with_message("全部文件分析完成");
-    Ok(())
+    // 各文件独立读取、独立输出，并发处理
+    let reports = files
+        .par_iter()
+        .map(|file_path| {
+            let file_options = AnalyzeOptions {
+                output: options.output.as_ref().map(|dir| {
+                    let mut output_path = dir.join(file_path.file_stem().unwrap_or_default());
+                    output_path.set_extension(options.format.extension());
+                    output_path
+                }),
+                ..options.clone()
+            };
+            let reports = analyze_single_file(&file_path.to_string_lossy(), geo, &file_options)
+                .map_err(|e| file_error(file_path, e))?;
+            on_file_done(file_path);
+            Ok(reports)
+        })
+        .collect::<Result<Vec<Vec<Report>>>>()?;
+
+    Ok(reports.into_iter().flatten().collect())
 }
 
+// 分析目录中所有 pcap 和 pcapng 文件并汇总成一份报告（多主机模式下每个内网主机一份）
 pub fn analyze_directory_merged(
     dir_path: &str,
-    api_url: &str,
-    tshark_tsv: &str,
-    company: Option<&str>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let path = Path::new(dir_path);
-    if !path.is_dir() {
-        eprintln!("❌ {} 不是一个目录", dir_path);
-        std::process::exit(1);
-    }
+    geo: &GeoSource,
+    options: &AnalyzeOptions,
+    on_file_done: &(dyn Fn(&Path) + Sync),
+) -> Result<Vec<Report>> {
+    let files = find_capture_files(dir_path)?;
 
     // 输出文件名
-    let output_csv = get_output_csv_path(dir_path);
+    let output_csv = match &options.output {
+        Some(output) => create_output_dir(output)?,
+        None => get_output_path(dir_path, options.format),
+    };
 
-    let files: Vec<_> = fs::read_dir(path)?
-        .filter_map(|entry| {
-            let entry = entry.ok()?;
-            let file_path = entry.path();
-            if file_path.is_file() {
-                if let Some(ext) = file_path.extension() {
-                    if ext == "pcap" || ext == "pcapng" {
-                        return Some(file_path);
-                    }
-                }
-            }
-            None
-        })
-        .collect();
+    if options.multi_host {
+        let results = files
+            .par_iter()
+            .map(|file_path| {
+                // 单个文件中没有内网主机时按空结果合并，全部文件都没有时才报错
+                let results = match parse_multi_host(&file_path.to_string_lossy(), options) {
+                    Err(Error::NoInternalHosts) => HostResults::new(),
+                    results => results.map_err(|e| file_error(file_path, e))?,
+                };
+                on_file_done(file_path);
+                Ok::<_, Error>(results)
+            })
+            .try_reduce(HashMap::new, |a, b| Ok(merge_host_results(a, b)))?;
+        if results.is_empty() {
+            return Err(Error::NoInternalHosts);
+        }
 
-    if files.is_empty() {
-        eprintln!("⚠️ 目录 {} 中没有找到 .pcap 或 .pcapng 文件", dir_path);
-        std::process::exit(0);
+        return write_host_outputs(&output_csv, results, geo, options);
     }
 
-    let pb = ProgressBar::new(files.len() as u64);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} {msg}")
-            .unwrap()
-            .progress_chars("#>-"),
-    );
+    // 并发解析各文件，再两两归并统计结果
+    let mut result = files
+        .par_iter()
+        .map(|file_path| {
+            let result = parse_and_aggregate(&file_path.to_string_lossy(), options)
+                .map_err(|e| file_error(file_path, e))?;
+            on_file_done(file_path);
+            Ok::<_, Error>(result)
+        })
+        .try_reduce(AggregateResult::default, |a, b| Ok(a.merge(b)))?;
+
+    // 合并后再标注解析域名，DNS 应答与对应流量可以在不同文件中
+    result.attribute_names();
+    let locations = enrich(geo, vec![&mut result.stats], options);
+    Ok(vec![write_report(
+        &output_csv,
+        &result,
+        locations.as_ref(),
+        options,
+    )?])
+}
 
-    let mut global_stats_map: HashMap<String, FlowStat> = HashMap::new();
-    let mut total_all = 0;
-    let mut up_all = 0;
-    let mut down_all = 0;
+// 目录中的文件出错时附上文件路径
+fn file_error(path: &Path, error: Error) -> Error {
+    Error::File {
+        path: path.to_path_buf(),
+        source: Box::new(error),
+    }
+}
 
-    for file_path in files {
-        pb.set_message(format!("分析文件: {}", file_path.display()));
-        let (stats_map, total, up, down, _local_ip) =
-            parse_and_aggregate(file_path.to_str().unwrap(), tshark_tsv,company)?;
+// 查询归属地；未配置归属地来源时返回 None，仍然输出结果，归属地列标记为不可用
+fn query_locations<'a>(
+    geo: &GeoSource,
+    ips: impl Iterator<Item = &'a IpAddr>,
+) -> Option<HashMap<IpAddr, GeoLocation>> {
+    geo.is_available().then(|| {
+        let ip_list: Vec<IpAddr> = ips.cloned().collect::<HashSet<_>>().into_iter().collect();
+        geo.query_locations(&ip_list)
+    })
+}
 
-        // 合并当前 stats_map 到 global_stats_map
-        for (ip, stat) in stats_map {
-            global_stats_map
-                .entry(ip)
-                .and_modify(|s| {
-                    s.total_pkts += stat.total_pkts;
-                    s.total_bytes += stat.total_bytes;
-                    s.up_pkts += stat.up_pkts;
-                    s.up_bytes += stat.up_bytes;
-                    s.down_pkts += stat.down_pkts;
-                    s.down_bytes += stat.down_bytes;
-                })
-                .or_insert(stat);
+/// 去掉低于 min_bytes 的 IP，查询归属地并应用过滤规则，返回归属地表（未配置归属地来源时为 None）。
+/// 有 ASN 规则时先查询全部 IP 再过滤，否则先过滤，减少在线查询次数
+pub fn enrich(
+    geo: &GeoSource,
+    mut stats_maps: Vec<&mut HashMap<IpAddr, FlowStat>>,
+    options: &AnalyzeOptions,
+) -> Option<HashMap<IpAddr, GeoLocation>> {
+    let filter = &options.filter;
+    for stats in stats_maps.iter_mut() {
+        stats.retain(|_, stat| stat.total_bytes >= options.min_bytes);
+    }
+    if !filter.needs_location() {
+        for stats in stats_maps.iter_mut() {
+            filter.apply(stats, None);
         }
-
-        total_all += total;
-        up_all += up;
-        down_all += down;
-        pb.inc(1);
+        return query_locations(geo, stats_maps.iter().flat_map(|stats| stats.keys()));
     }
 
-    // 查询归属地
-    if !api_url.is_empty() {
-        let ip_list: Vec<String> = global_stats_map.keys().cloned().collect();
-        let locations = location::query_ip_locations(&ip_list, 100, api_url);
+    let locations = query_locations(geo, stats_maps.iter().flat_map(|stats| stats.keys()));
+    for stats in stats_maps.iter_mut() {
+        filter.apply(stats, locations.as_ref());
+    }
+    locations
+}
 
-        csv_output::write_csv(&output_csv, &global_stats_map, &locations, total_all, up_all, down_all).unwrap();
-        println!("✅ 所有文件分析完成，结果已保存到 {}", &output_csv);
+/// 写出主报告及按选项启用的附加 CSV
+pub fn write_report(
+    output_csv: &str,
+    result: &AggregateResult,
+    locations: Option<&HashMap<IpAddr, GeoLocation>>,
+    options: &AnalyzeOptions,
+) -> Result<Report> {
+    match options.format {
+        OutputFormat::Csv => csv_output::write_csv(
+            output_csv,
+            &result.stats,
+            locations,
+            &options.csv,
+            (result.total, result.up, result.down),
+        )?,
+        OutputFormat::Json => {
+            json_output::write_json(output_csv, result, locations, options.csv.sort)?
+        }
+        OutputFormat::Jsonl => {
+            json_output::write_jsonl(output_csv, &result.stats, locations, options.csv.sort)?
+        }
+        OutputFormat::Xlsx => xlsx_output::write_xlsx(
+            output_csv,
+            result,
+            locations,
+            options.csv.sort,
+            options.csv.bytes.base,
+        )?,
+        OutputFormat::Html => {
+            html_output::write_html(output_csv, result, locations, options.csv.sort)?
+        }
+    }
+    if options.csv.port_details {
+        csv_output::write_ports_csv(
+            &csv_output::sibling_csv_path(output_csv, "ports"),
+            &result.stats,
+            locations,
+            &options.csv,
+        )?;
+    }
+    if options.csv.domains {
+        csv_output::write_domains_csv(
+            &csv_output::sibling_csv_path(output_csv, "domains"),
+            &result.stats,
+            false,
+            &options.csv.bytes,
+        )?;
+        csv_output::write_domains_csv(
+            &csv_output::sibling_csv_path(output_csv, "registrable"),
+            &result.stats,
+            true,
+            &options.csv.bytes,
+        )?;
     }
+    if options.interval.is_some() {
+        csv_output::write_timeseries_csv(
+            &csv_output::sibling_csv_path(output_csv, "timeseries"),
+            &result.stats,
+            locations,
+            options.csv.sort,
+        )?;
+    }
+    if let Some(flows) = &result.flows {
+        csv_output::write_flows_csv(
+            &csv_output::sibling_csv_path(output_csv, "flows"),
+            flows,
+            &options.csv.bytes,
+        )?;
+    }
+    Ok(Report {
+        output: PathBuf::from(output_csv),
+        local: result.local.clone(),
+        rows: result.stats.len(),
+        total: result.total,
+        up: result.up,
+        down: result.down,
+    })
+}
 
-    pb.finish_with_message("全部文件分析完成");
-    Ok(())
+// 多主机模式：所有主机的远端 IP 一起查询归属地，再为每个主机写出 <文件名>_<主机>.csv
+fn write_host_outputs(
+    output_csv: &str,
+    mut results: HostResults,
+    geo: &GeoSource,
+    options: &AnalyzeOptions,
+) -> Result<Vec<Report>> {
+    for result in results.values_mut() {
+        result.attribute_names();
+    }
+    let locations = enrich(
+        geo,
+        results.values_mut().map(|r| &mut r.stats).collect(),
+        options,
+    );
+    // 过滤后没有剩余行的主机不再输出
+    results.retain(|_, result| !result.stats.is_empty());
+
+    let mut hosts: Vec<&IpAddr> = results.keys().collect();
+    hosts.sort();
+    hosts
+        .into_iter()
+        .map(|host| {
+            // IPv6 地址中的冒号不能出现在 Windows 文件名中
+            let host_label = host.to_string().replace(':', "-");
+            let host_csv =
+                csv_output::sibling_path(output_csv, &host_label, options.format.extension());
+            write_report(&host_csv, &results[host], locations.as_ref(), options)
+        })
+        .collect()
 }
 
+/// 查询单个公网 IP 的归属地，查询失败时返回 Ok(None)
+pub fn lookup_ip(ip: &str, geo: &GeoSource) -> Result<Option<GeoLocation>> {
+    let ip = location::parse_public_ip(ip)?;
+    Ok(geo.query_locations(&[ip]).remove(&ip))
+}
 
-pub fn run_analysis_one_ip(args: &String, api_url: &str) {
-    if let Some(data) = location::query_single_ip(args, api_url) {
-        println!("IP: {}", data.ip);
-        println!("位置信息: {}{}{}{}", data.country, data.province, data.city, data.isp);
-    } else {
-        println!("未能查询到该 IP 的归属信息");
+// 创建指定输出文件所在的目录，返回输出路径
+fn create_output_dir(output: &Path) -> Result<String> {
+    if let Some(parent) = output.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
     }
-    std::process::exit(0);
+    Ok(output.to_string_lossy().to_string())
 }
 
 //获取绝对文件夹路径
-fn get_output_csv_path(dir_path: &str) -> String {
+fn get_output_path(dir_path: &str, format: OutputFormat) -> String {
     // 将输入路径转为绝对路径
     let abs_path = fs::canonicalize(dir_path).unwrap_or_else(|_| PathBuf::from(dir_path));
 
     // 获取目录名（即最后一级目录）
-    let dir_name = abs_path.file_name()
-        .or_else(|| abs_path.components().last().map(|c| c.as_os_str()))
+    let dir_name = abs_path
+        .file_name()
+        .or_else(|| abs_path.components().next_back().map(|c| c.as_os_str()))
         .unwrap_or_else(|| std::ffi::OsStr::new("output"));
 
-    // 构造输出路径（在当前目录下生成 dir_name.csv）
+    // 构造输出路径（在当前目录下生成 dir_name.csv，扩展名随输出格式）
     let mut output_path = abs_path.join(dir_name);
-    output_path.set_extension("csv");
+    output_path.set_extension(format.extension());
 
     output_path.to_string_lossy().to_string()
-}
\ No newline at end of file
+}
@@ -1,17 +1,223 @@
-use crate::stats::FlowStat;
+use crate::domains;
+use crate::flows::{format_tcp_flags, format_timestamp, format_timestamp_secs, FlowTable};
+use crate::location::GeoLocation;
+use crate::stats::{format_port_key, protocol_name, FlowStat, TimeBucket};
 use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::Path;
 use std::fs::File;
 use std::io::{BufWriter, Write};
+use clap::ValueEnum;
 use csv::Writer;
 
+/// CSV 输出选项
+#[derive(Debug, Clone, Default)]
+pub struct CsvOptions {
+    /// 归属地输出为旧版的单列拼接字符串，而不是国家/省份/城市/运营商分列
+    pub legacy_location: bool,
+    /// 额外输出 <文件名>_ports.csv，列出每个 IP 各协议端口的流量
+    pub port_details: bool,
+    /// 额外输出 <文件名>_flows.csv 五元组会话表
+    pub flows: bool,
+    /// 额外输出按域名汇总的 <文件名>_domains.csv 与按可注册域名汇总的 <文件名>_registrable.csv
+    pub domains: bool,
+    /// 数据量列的写法
+    pub bytes: ByteFormat,
+    /// 主表输出的列及顺序，为空时输出默认列
+    pub columns: Vec<Column>,
+    /// 报告中各 IP 的排序依据
+    pub sort: SortKey,
+    /// 指定时业务说明合并为一列，域名之间用该分隔符连接
+    pub domain_separator: Option<String>,
+}
+
+/// 主表的列，命令行中为 kebab-case 名称（如 total-bytes）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Column {
+    Ip,
+    TotalPkts,
+    TotalBytes,
+    UpPkts,
+    UpBytes,
+    DownPkts,
+    DownBytes,
+    /// 主要端口
+    MainPort,
+    /// 端口分布
+    Ports,
+    FirstSeen,
+    LastSeen,
+    /// 归属域名（每个 IP 一个），规则同按域名汇总的报表
+    Domain,
+    /// 解析域名
+    ResolvedNames,
+    /// CNAME链
+    CnameChains,
+    /// 业务说明：数据包中出现的域名，默认每个域名一列
+    Domains,
+    /// 归属地，按选项分列或合并为一列
+    Location,
+    /// 匹配规则
+    MatchedRule,
+}
+
+impl Column {
+    fn title(self) -> &'static str {
+        match self {
+            Column::Ip => "IP",
+            Column::TotalPkts => "总数据包",
+            Column::TotalBytes => "总数据量",
+            Column::UpPkts => "上行数据包",
+            Column::UpBytes => "上行数据量",
+            Column::DownPkts => "下行数据包",
+            Column::DownBytes => "下行数据量",
+            Column::MainPort => "主要端口",
+            Column::Ports => "端口分布",
+            Column::FirstSeen => "首次出现",
+            Column::LastSeen => "最后出现",
+            Column::Domain => "归属域名",
+            Column::ResolvedNames => "解析域名",
+            Column::CnameChains => "CNAME链",
+            Column::Domains => "业务说明",
+            Column::Location => "归属地",
+            Column::MatchedRule => "匹配规则",
+        }
+    }
+}
+
+/// 未指定列时主表的列
+pub const DEFAULT_COLUMNS: [Column; 16] = [
+    Column::Ip,
+    Column::TotalPkts,
+    Column::TotalBytes,
+    Column::UpPkts,
+    Column::UpBytes,
+    Column::DownPkts,
+    Column::DownBytes,
+    Column::MainPort,
+    Column::Ports,
+    Column::FirstSeen,
+    Column::LastSeen,
+    Column::ResolvedNames,
+    Column::CnameChains,
+    Column::Domains,
+    Column::Location,
+    Column::MatchedRule,
+];
+
+/// 报告中各 IP 的排序依据，数值按降序，归属地按升序
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum SortKey {
+    /// 总数据包
+    #[default]
+    Packets,
+    /// 总数据量
+    Bytes,
+    /// 上行数据量
+    Up,
+    /// 下行数据量
+    Down,
+    /// 业务说明中的域名数
+    Domains,
+    /// 归属地（国家、省份、城市、运营商），查不到的排在最后
+    Location,
+}
+
+/// 数据量列的单位
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ByteUnit {
+    /// 自动选择单位的可读格式，如 "12.34 MB"
+    #[default]
+    Human,
+    /// 字节数整数
+    Bytes,
+    Kb,
+    Mb,
+    Gb,
+}
+
+/// 数据量列的格式：可读格式，或固定单位的数值，便于在表格中求和与排序
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteFormat {
+    pub unit: ByteUnit,
+    /// 换算进制，1000 或 1024
+    pub base: u64,
+    /// 数值列之后再输出一列可读格式
+    pub human_column: bool,
+}
+
+impl Default for ByteFormat {
+    fn default() -> Self {
+        ByteFormat { unit: ByteUnit::Human, base: 1024, human_column: false }
+    }
+}
+
+impl ByteFormat {
+    /// 按所选单位格式化，固定单位时保留三位小数且不带单位
+    pub fn format(&self, bytes: u64) -> String {
+        let divisor = match self.unit {
+            ByteUnit::Human => return self.human(bytes),
+            ByteUnit::Bytes => return bytes.to_string(),
+            ByteUnit::Kb => self.base,
+            ByteUnit::Mb => self.base.pow(2),
+            ByteUnit::Gb => self.base.pow(3),
+        };
+        format!("{:.3}", bytes as f64 / divisor as f64)
+    }
+
+    /// 可读格式，如 "12.34 MB"
+    pub fn human(&self, bytes: u64) -> String {
+        let kb = self.base as f64;
+        let mb = kb * kb;
+        let bytes_f64 = bytes as f64;
+
+        if bytes_f64 >= mb {
+            format!("{:.2} MB", bytes_f64 / mb)
+        } else if bytes_f64 >= kb {
+            format!("{:.2} KB", bytes_f64 / kb)
+        } else {
+            format!("{} B", bytes)
+        }
+    }
+
+    /// 数据量列的表头，固定单位时注明单位，如 "总数据量(MB)"；启用可读列时附带 "总数据量(可读)"
+    pub fn headers(&self, name: &str) -> Vec<String> {
+        let unit = match self.unit {
+            ByteUnit::Human => return vec![name.to_string()],
+            ByteUnit::Bytes => "字节",
+            ByteUnit::Kb => "KB",
+            ByteUnit::Mb => "MB",
+            ByteUnit::Gb => "GB",
+        };
+        let mut headers = vec![format!("{}({})", name, unit)];
+        if self.human_column {
+            headers.push(format!("{}(可读)", name));
+        }
+        headers
+    }
+
+    /// 与 headers 对应的各列的值
+    pub fn fields(&self, bytes: u64) -> Vec<String> {
+        let mut fields = vec![self.format(bytes)];
+        if self.unit != ByteUnit::Human && self.human_column {
+            fields.push(self.human(bytes));
+        }
+        fields
+    }
+}
+
+// 端口分布列中列出的协议端口数
+const TOP_PORTS: usize = 5;
+
 pub fn write_csv(
     output_csv: &str,
-    stats_map: &HashMap<String, FlowStat>,
-    locations: &HashMap<String, String>,
-    total: u64,
-    up: u64,
-    down: u64,
-) -> Result<(), Box<dyn std::error::Error>> {
+    stats_map: &HashMap<IpAddr, FlowStat>,
+    // None 表示未查询归属地
+    locations: Option<&HashMap<IpAddr, GeoLocation>>,
+    options: &CsvOptions,
+    // (总流量, 上行, 下行)
+    totals: (u64, u64, u64),
+) -> crate::Result<()> {
     let file = File::create(output_csv)?;
     let mut writer = BufWriter::new(file);
 
@@ -20,113 +226,382 @@ pub fn write_csv(
 
     let mut wtr = Writer::from_writer(writer);
 
-    let sorted_stats = sort_stats_by_total_pkts(stats_map);
+    let sorted_stats = sort_stats(stats_map, options.sort, locations);
 
-    // 1️⃣ 计算最大域名数
-    let max_domains = sorted_stats
-        .iter()
-        .map(|(_, stat)| stat.domains.len())
-        .max()
-        .unwrap_or(0);
+    // 1️⃣ 确定输出的列：未指定时为默认列，匹配规则列仅在指定了包含规则时输出
+    let columns: Vec<Column> = if options.columns.is_empty() {
+        let with_rule = sorted_stats.iter().any(|(_, stat)| !stat.matched_rule.is_empty());
+        DEFAULT_COLUMNS.into_iter().filter(|c| *c != Column::MatchedRule || with_rule).collect()
+    } else {
+        options.columns.clone()
+    };
 
-    // 2️⃣ 写表头
-    let mut header = vec![
-        "IP",
-        "总数据包",
-        "总数据量",
-        "上行数据包",
-        "上行数据量",
-        "下行数据包",
-        "下行数据量",
-    ];
+    let table = Table {
+        options,
+        // 业务说明分列输出时按最大域名数补齐
+        max_domains: sorted_stats.iter().map(|(_, stat)| stat.domains.len()).max().unwrap_or(0),
+        // 有任一 IP 查到 ASN 时才输出 ASN 列
+        with_asn: locations.is_some_and(|locs| locs.values().any(|loc| loc.asn.is_some())),
+    };
 
-    for _i in 0..max_domains {
-        header.push("业务说明");
+    // 总计行：“总计”写在第一列；第一列本身有总计值（如数据量列）时，在最前面加一个标签列
+    let mut summary: Vec<String> = columns.iter().flat_map(|column| table.summary(*column, totals)).collect();
+    let label_column = summary.first().is_some_and(|cell| !cell.is_empty());
+    if label_column {
+        summary.insert(0, "总计".to_string());
+    } else if let Some(first) = summary.first_mut() {
+        *first = "总计".to_string();
     }
-    header.push("归属地");
+    let label = |mut row: Vec<String>| {
+        if label_column {
+            row.insert(0, String::new());
+        }
+        row
+    };
 
-    wtr.write_record(&header)?;
+    // 2️⃣ 写表头
+    let header: Vec<String> = columns.iter().flat_map(|column| table.header(*column)).collect();
+    wtr.write_record(label(header))?;
 
     // 3️⃣ 写数据行
     for (ip, stat) in sorted_stats.iter() {
-        
-        let mut record = vec![
-            ip.clone(),
-            stat.total_pkts.to_string(),
-            format_bytes(stat.total_bytes),
-            stat.up_pkts.to_string(),
-            format_bytes(stat.up_bytes),
-            stat.down_pkts.to_string(),
-            format_bytes(stat.down_bytes),
-        ];
+        let location = match locations {
+            Some(locations) => locations.get(ip).ok_or("未知"),
+            None => Err("不可用"),
+        };
+        let record: Vec<String> = columns
+            .iter()
+            .flat_map(|column| table.fields(*column, ip, stat, location))
+            .collect();
+        wtr.write_record(label(record))?;
+    }
+
+    // 4️⃣ 写总计行
+    wtr.write_record(&summary)?;
+
+    wtr.flush()?;
+    Ok(())
+}
 
-        // 拿到所有域名并排序
-        let mut domain_list: Vec<_> = stat.domains.iter().cloned().collect();
-        domain_list.sort();
+// 主表各列的表头与取值
+struct Table<'a> {
+    options: &'a CsvOptions,
+    max_domains: usize,
+    with_asn: bool,
+}
 
-        for domain in &domain_list {
-            record.push(domain.clone());
+impl Table<'_> {
+    fn header(&self, column: Column) -> Vec<String> {
+        let bytes = &self.options.bytes;
+        match column {
+            Column::TotalBytes => bytes.headers("总数据量"),
+            Column::UpBytes => bytes.headers("上行数据量"),
+            Column::DownBytes => bytes.headers("下行数据量"),
+            Column::Domains => vec!["业务说明".to_string(); self.domain_columns()],
+            Column::Location => location_header(self.options.legacy_location, self.with_asn)
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            _ => vec![column.title().to_string()],
         }
+    }
 
-        // 不足补空
-        while record.len() < 7 + max_domains {
-            record.push("".to_string());
+    fn fields(&self, column: Column, ip: &IpAddr, stat: &FlowStat, location: Result<&GeoLocation, &str>) -> Vec<String> {
+        let bytes = &self.options.bytes;
+        let field = match column {
+            Column::Ip => ip.to_string(),
+            Column::TotalPkts => stat.total_pkts.to_string(),
+            Column::TotalBytes => return bytes.fields(stat.total_bytes),
+            Column::UpPkts => stat.up_pkts.to_string(),
+            Column::UpBytes => return bytes.fields(stat.up_bytes),
+            Column::DownPkts => stat.down_pkts.to_string(),
+            Column::DownBytes => return bytes.fields(stat.down_bytes),
+            Column::MainPort => stat.sorted_ports().first().map(|(key, _)| format_port_key(*key)).unwrap_or_default(),
+            // 按数据量排序的协议端口，如 "TCP/443 1.20 MB; UDP/443 300.00 KB"
+            Column::Ports => stat
+                .sorted_ports()
+                .iter()
+                .take(TOP_PORTS)
+                .map(|(key, port)| format!("{} {}", format_port_key(*key), bytes.human(port.bytes)))
+                .collect::<Vec<_>>()
+                .join("; "),
+            Column::FirstSeen => format_timestamp(stat.first_seen),
+            Column::LastSeen => format_timestamp(stat.last_seen),
+            Column::Domain => domains::primary_domain(stat).unwrap_or_default().to_string(),
+            Column::ResolvedNames => stat.resolved_names.iter().cloned().collect::<Vec<_>>().join("; "),
+            Column::CnameChains => stat.cname_chains.iter().cloned().collect::<Vec<_>>().join("; "),
+            Column::Domains => {
+                // 拿到所有域名并排序
+                let mut domain_list: Vec<String> = stat.domains.iter().cloned().collect();
+                domain_list.sort();
+                if let Some(separator) = &self.options.domain_separator {
+                    return vec![domain_list.join(separator)];
+                }
+                // 不足补空
+                domain_list.resize(self.max_domains, "".to_string());
+                return domain_list;
+            }
+            Column::Location => return location_fields(location, self.options.legacy_location, self.with_asn),
+            Column::MatchedRule => stat.matched_rule.clone(),
+        };
+        vec![field]
+    }
+
+    fn summary(&self, column: Column, (total, up, down): (u64, u64, u64)) -> Vec<String> {
+        let bytes = &self.options.bytes;
+        // “总计”标签由 write_csv 写在第一列
+        match column {
+            Column::TotalBytes => bytes.fields(total),
+            Column::UpBytes => bytes.fields(up),
+            Column::DownBytes => bytes.fields(down),
+            _ => vec!["".to_string(); self.header(column).len()],
         }
+    }
+
+    // 业务说明占用的列数：合并为一列时固定为一列
+    fn domain_columns(&self) -> usize {
+        if self.options.domain_separator.is_some() { 1 } else { self.max_domains }
+    }
+}
+
+/// 写出每个 IP 各协议端口的流量明细，IP 顺序与主表一致
+pub fn write_ports_csv(
+    output_csv: &str,
+    stats_map: &HashMap<IpAddr, FlowStat>,
+    locations: Option<&HashMap<IpAddr, GeoLocation>>,
+    options: &CsvOptions,
+) -> crate::Result<()> {
+    let bytes = &options.bytes;
+    let file = File::create(output_csv)?;
+    let mut writer = BufWriter::new(file);
+
+    // 写入 UTF-8 BOM
+    writer.write_all(b"\xEF\xBB\xBF")?;
 
-        record.push(locations.get(ip).cloned().unwrap_or_else(|| "未知".to_string()));
+    let mut wtr = Writer::from_writer(writer);
+    let mut header = ["IP", "协议", "端口", "数据包"].map(String::from).to_vec();
+    header.extend(bytes.headers("数据量"));
+    wtr.write_record(&header)?;
 
+    for (ip, stat) in sort_stats(stats_map, options.sort, locations) {
+        for ((proto, port), port_stat) in stat.sorted_ports() {
+            let mut record = vec![
+                ip.to_string(),
+                protocol_name(proto),
+                if port == 0 { "".to_string() } else { port.to_string() },
+                port_stat.pkts.to_string(),
+            ];
+            record.extend(bytes.fields(port_stat.bytes));
+            wtr.write_record(&record)?;
+        }
+    }
+
+    wtr.flush()?;
+    Ok(())
+}
+
+/// 按归属域名汇总写出，registrable 为 true 时按可注册域名（eTLD+1）汇总；每个 IP 只归属一个域名
+pub fn write_domains_csv(
+    output_csv: &str,
+    stats_map: &HashMap<IpAddr, FlowStat>,
+    registrable: bool,
+    bytes: &ByteFormat,
+) -> crate::Result<()> {
+    let file = File::create(output_csv)?;
+    let mut writer = BufWriter::new(file);
+
+    // 写入 UTF-8 BOM
+    writer.write_all(b"\xEF\xBB\xBF")?;
+
+    let mut wtr = Writer::from_writer(writer);
+    let mut header = vec![if registrable { "可注册域名" } else { "域名" }.to_string()];
+    if !registrable {
+        header.push("可注册域名".to_string());
+    }
+    header.extend(["IP数", "IP列表"].map(String::from));
+    for direction in ["总", "上行", "下行"] {
+        header.push(format!("{}数据包", direction));
+        header.extend(bytes.headers(&format!("{}数据量", direction)));
+    }
+    wtr.write_record(&header)?;
+
+    for (domain, stat) in domains::aggregate_by_domain(stats_map, registrable) {
+        let mut record = vec![domain.clone()];
+        if !registrable {
+            record.push(if domain == domains::UNATTRIBUTED { domain.clone() } else { domains::registrable_domain(&domain) });
+        }
+        record.extend([
+            stat.ips.len().to_string(),
+            stat.ips.iter().map(|ip| ip.to_string()).collect::<Vec<_>>().join("; "),
+        ]);
+        for (pkts, size) in [
+            (stat.total_pkts, stat.total_bytes),
+            (stat.up_pkts, stat.up_bytes),
+            (stat.down_pkts, stat.down_bytes),
+        ] {
+            record.push(pkts.to_string());
+            record.extend(bytes.fields(size));
+        }
         wtr.write_record(&record)?;
     }
 
-    // 4️⃣ 写总计行
-    let mut summary = vec![
-        "总计".to_string(),
-        "".to_string(),
-        format_bytes(total),
-        "".to_string(),
-        format_bytes(up),
-        "".to_string(),
-        format_bytes(down),
-    ];
-    while summary.len() < 7 + max_domains {
-        summary.push("".to_string());
-    }
-    
-    // 补上“归属地”列
-    summary.push("".to_string());
-    
-    wtr.write_record(&summary)?;
+    wtr.flush()?;
+    Ok(())
+}
+
+/// 写出五元组会话表，按开始时间排序
+pub fn write_flows_csv(output_csv: &str, flows: &FlowTable, bytes: &ByteFormat) -> crate::Result<()> {
+    let file = File::create(output_csv)?;
+    let mut writer = BufWriter::new(file);
+
+    // 写入 UTF-8 BOM
+    writer.write_all(b"\xEF\xBB\xBF")?;
+
+    let mut wtr = Writer::from_writer(writer);
+    let mut header = ["源IP", "源端口", "目的IP", "目的端口", "协议", "开始时间", "结束时间", "持续时间(秒)"]
+        .map(String::from)
+        .to_vec();
+    for direction in ["正向", "反向"] {
+        header.push(format!("{}数据包", direction));
+        header.extend(bytes.headers(&format!("{}数据量", direction)));
+    }
+    header.push("TCP标志".to_string());
+    wtr.write_record(&header)?;
 
+    let port = |p: u16| if p == 0 { "".to_string() } else { p.to_string() };
+    for (key, flow) in flows.sorted() {
+        let mut record = vec![
+            key.src.to_string(),
+            port(key.src_port),
+            key.dst.to_string(),
+            port(key.dst_port),
+            protocol_name(key.proto),
+            format_timestamp(flow.first_ts),
+            format_timestamp(flow.last_ts),
+            format!("{:.6}", flow.duration()),
+        ];
+        for (pkts, size) in [(flow.fwd_pkts, flow.fwd_bytes), (flow.rev_pkts, flow.rev_bytes)] {
+            record.push(pkts.to_string());
+            record.extend(bytes.fields(size));
+        }
+        record.push(format_tcp_flags(flow.tcp_flags));
+        wtr.write_record(&record)?;
+    }
 
     wtr.flush()?;
     Ok(())
 }
 
+/// 写出长格式的时间序列：每行为一个时间段内与一个远端 IP 的流量，数据量为字节数，便于绘图
+pub fn write_timeseries_csv(
+    output_csv: &str,
+    stats_map: &HashMap<IpAddr, FlowStat>,
+    locations: Option<&HashMap<IpAddr, GeoLocation>>,
+    sort: SortKey,
+) -> crate::Result<()> {
+    let file = File::create(output_csv)?;
+    let mut writer = BufWriter::new(file);
 
-pub fn format_bytes(bytes: u64) -> String {
-    const KB: f64 = 1024.0;
-    const MB: f64 = 1024.0 * 1024.0;
+    // 写入 UTF-8 BOM
+    writer.write_all(b"\xEF\xBB\xBF")?;
 
-    let bytes_f64 = bytes as f64;
+    let mut wtr = Writer::from_writer(writer);
+    wtr.write_record(["时间", "IP", "上行数据包", "上行字节数", "下行数据包", "下行字节数", "总字节数"])?;
 
-    if bytes_f64 >= MB {
-        format!("{:.2} MB", bytes_f64 / MB)
-    } else if bytes_f64 >= KB {
-        format!("{:.2} KB", bytes_f64 / KB)
-    } else {
-        format!("{} B", bytes)
+    // 按时间段排序，同一时间段内按 IP 在主表中的顺序
+    let mut rows: Vec<(i64, usize, IpAddr, TimeBucket)> = Vec::new();
+    for (order, (ip, stat)) in sort_stats(stats_map, sort, locations).into_iter().enumerate() {
+        for (start, bucket) in &stat.buckets {
+            rows.push((*start, order, ip, *bucket));
+        }
     }
+    rows.sort_by_key(|(start, order, _, _)| (*start, *order));
+
+    for (start, _, ip, bucket) in rows {
+        wtr.write_record(&[
+            format_timestamp_secs(start),
+            ip.to_string(),
+            bucket.up_pkts.to_string(),
+            bucket.up_bytes.to_string(),
+            bucket.down_pkts.to_string(),
+            bucket.down_bytes.to_string(),
+            (bucket.up_bytes + bucket.down_bytes).to_string(),
+        ])?;
+    }
+
+    wtr.flush()?;
+    Ok(())
+}
+
+/// 由主报告路径得到附加 CSV 的路径，如 a.csv -> a_ports.csv、a.json -> a_ports.csv
+pub fn sibling_csv_path(output: &str, suffix: &str) -> String {
+    sibling_path(output, suffix, "csv")
+}
+
+/// 由主报告路径得到同目录下带后缀的路径，去掉原扩展名后加上 _<suffix>.<extension>
+pub fn sibling_path(output: &str, suffix: &str, extension: &str) -> String {
+    let path = Path::new(output);
+    let stem = match path.extension() {
+        Some(ext) => &output[..output.len() - ext.len() - 1],
+        None => output,
+    };
+    format!("{}_{}.{}", stem, suffix, extension)
+}
+
+fn location_header(legacy: bool, with_asn: bool) -> Vec<&'static str> {
+    let mut header = if legacy { vec!["归属地"] } else { vec!["国家", "省份", "城市", "运营商"] };
+    if with_asn {
+        header.extend(["ASN", "ASN组织"]);
+    }
+    header
 }
 
-pub fn sort_stats_by_total_pkts(
-    stats: &HashMap<String, FlowStat>,
-) -> Vec<(String, FlowStat)> {
-    let mut vec: Vec<(String, FlowStat)> = stats
-        .iter()
-        .map(|(ip, stat)| (ip.clone(), stat.clone()))
-        .collect();
+// 归属地各列的值；查询不到或未查询时在第一列填写原因，其余列留空。
+// 旧版格式的单列只含国家省份城市运营商，ASN 仍单独成列
+fn location_fields(location: Result<&GeoLocation, &str>, legacy: bool, with_asn: bool) -> Vec<String> {
+    let width = location_header(legacy, with_asn).len();
+    let mut fields = match location {
+        Ok(loc) => {
+            let mut fields = if legacy {
+                vec![loc.combined()]
+            } else {
+                vec![loc.country.clone(), loc.province.clone(), loc.city.clone(), loc.isp.clone()]
+            };
+            if with_asn {
+                fields.push(loc.asn.map(|asn| format!("AS{}", asn)).unwrap_or_default());
+                fields.push(loc.org.clone());
+            }
+            fields
+        }
+        Err(reason) => vec![reason.to_string()],
+    };
+    fields.resize(width, "".to_string());
+    fields
+}
 
-    vec.sort_by(|a, b| b.1.total_pkts.cmp(&a.1.total_pkts)); // 降序
+/// 按排序依据排列各 IP，相同时按 IP 排列；locations 仅在按归属地排序时使用
+pub fn sort_stats<'a>(
+    stats: &'a HashMap<IpAddr, FlowStat>,
+    key: SortKey,
+    locations: Option<&HashMap<IpAddr, GeoLocation>>,
+) -> Vec<(IpAddr, &'a FlowStat)> {
+    let mut vec: Vec<(IpAddr, &FlowStat)> = stats.iter().map(|(ip, stat)| (*ip, stat)).collect();
+
+    let location = |ip: &IpAddr| locations.and_then(|locs| locs.get(ip)).filter(|loc| !loc.is_empty());
+    vec.sort_by(|(a_ip, a), (b_ip, b)| {
+        let order = match key {
+            SortKey::Packets => b.total_pkts.cmp(&a.total_pkts),
+            SortKey::Bytes => b.total_bytes.cmp(&a.total_bytes),
+            SortKey::Up => b.up_bytes.cmp(&a.up_bytes),
+            SortKey::Down => b.down_bytes.cmp(&a.down_bytes),
+            SortKey::Domains => b.domains.len().cmp(&a.domains.len()),
+            SortKey::Location => match (location(a_ip), location(b_ip)) {
+                (Some(a), Some(b)) => (&a.country, &a.province, &a.city, &a.isp).cmp(&(&b.country, &b.province, &b.city, &b.isp)),
+                (a, b) => b.is_some().cmp(&a.is_some()),
+            },
+        };
+        order.then_with(|| a_ip.cmp(b_ip))
+    });
 
     vec
-}
\ No newline at end of file
+}
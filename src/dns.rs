@@ -0,0 +1,151 @@
+use crate::packet::DnsAnswer;
+use std::collections::{BTreeSet, HashMap};
+use std::net::IpAddr;
+
+// 回溯 CNAME 链的最大深度，防止异常应答中的循环引用
+const MAX_CNAME_DEPTH: usize = 16;
+
+/// 由 DNS 应答建立的解析表，用于把域名归属到其解析出的 IP
+#[derive(Debug, Default, Clone)]
+pub struct ResolutionTable {
+    // 地址 -> 直接解析到该地址的域名（A/AAAA 记录的所有者）
+    addrs: HashMap<IpAddr, BTreeSet<String>>,
+    // 规范名 -> 指向它的别名
+    aliases: HashMap<String, BTreeSet<String>>,
+}
+
+impl ResolutionTable {
+    pub fn push(&mut self, answers: &[DnsAnswer]) {
+        for answer in answers {
+            match answer {
+                DnsAnswer::Addr(name, ip) => {
+                    self.addrs.entry(*ip).or_default().insert(normalize(name));
+                }
+                DnsAnswer::Cname(alias, target) => {
+                    self.aliases.entry(normalize(target)).or_default().insert(normalize(alias));
+                }
+            }
+        }
+    }
+
+    /// 并入另一份解析表，合并多个文件时使用
+    pub fn merge(&mut self, other: ResolutionTable) {
+        for (ip, names) in other.addrs {
+            self.addrs.entry(ip).or_default().extend(names);
+        }
+        for (target, aliases) in other.aliases {
+            self.aliases.entry(target).or_default().extend(aliases);
+        }
+    }
+
+    /// 解析到该地址的全部 CNAME 链，每条链从查询的域名开始、到 A/AAAA 记录的所有者结束
+    pub fn chains(&self, ip: &IpAddr) -> Vec<Vec<String>> {
+        let mut chains = Vec::new();
+        for owner in self.addrs.get(ip).into_iter().flatten() {
+            self.collect_chains(vec![owner.clone()], &mut chains);
+        }
+        chains
+    }
+
+    // 沿别名向上回溯，链按从别名到规范名的顺序保存
+    fn collect_chains(&self, chain: Vec<String>, chains: &mut Vec<Vec<String>>) {
+        let head = &chain[0];
+        let aliases: Vec<&String> = self
+            .aliases
+            .get(head)
+            .into_iter()
+            .flatten()
+            .filter(|alias| !chain.contains(alias))
+            .collect();
+
+        if aliases.is_empty() || chain.len() >= MAX_CNAME_DEPTH {
+            chains.push(chain);
+            return;
+        }
+        for alias in aliases {
+            let mut longer = Vec::with_capacity(chain.len() + 1);
+            longer.push(alias.clone());
+            longer.extend(chain.iter().cloned());
+            self.collect_chains(longer, chains);
+        }
+    }
+}
+
+/// CNAME 链的显示形式，如 "www.example.com -> example.cdn.net"
+pub fn format_chain(chain: &[String]) -> String {
+    chain.join(" -> ")
+}
+
+// 域名不区分大小写，去掉末尾的根点
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(name: &str, ip: &str) -> DnsAnswer {
+        DnsAnswer::Addr(name.to_string(), ip.parse().unwrap())
+    }
+
+    fn cname(alias: &str, target: &str) -> DnsAnswer {
+        DnsAnswer::Cname(alias.to_string(), target.to_string())
+    }
+
+    fn chains(table: &ResolutionTable, ip: &str) -> Vec<String> {
+        table.chains(&ip.parse().unwrap()).iter().map(|chain| format_chain(chain)).collect()
+    }
+
+    #[test]
+    fn chains_follow_cnames_to_the_queried_name() {
+        let mut table = ResolutionTable::default();
+        table.push(&[
+            cname("WWW.Example.com.", "www.example.com.cdn.net"),
+            cname("www.example.com.cdn.net", "e1.akamai.net."),
+            addr("e1.akamai.net", "23.45.67.89"),
+            // 另一个别名指向同一规范名
+            cname("img.example.com", "e1.akamai.net"),
+            addr("direct.example.org", "1.1.1.1"),
+        ]);
+
+        assert_eq!(
+            chains(&table, "23.45.67.89"),
+            [
+                "img.example.com -> e1.akamai.net",
+                "www.example.com -> www.example.com.cdn.net -> e1.akamai.net",
+            ]
+        );
+        assert_eq!(chains(&table, "1.1.1.1"), ["direct.example.org"]);
+        assert!(chains(&table, "8.8.8.8").is_empty());
+    }
+
+    #[test]
+    fn cname_loops_terminate() {
+        let mut table = ResolutionTable::default();
+        table.push(&[cname("a.example", "b.example"), cname("b.example", "a.example"), addr("a.example", "10.0.0.1")]);
+        assert_eq!(chains(&table, "10.0.0.1"), ["b.example -> a.example"]);
+
+        // 过长的别名链在最大深度处截断
+        let mut table = ResolutionTable::default();
+        for i in 0..40 {
+            table.push(&[cname(&format!("n{}.example", i + 1), &format!("n{}.example", i))]);
+        }
+        table.push(&[addr("n0.example", "10.0.0.2")]);
+        let found = table.chains(&"10.0.0.2".parse().unwrap());
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].len(), MAX_CNAME_DEPTH);
+    }
+
+    #[test]
+    fn merge_combines_tables() {
+        // CNAME 与地址记录分别出现在两个文件中
+        let mut first = ResolutionTable::default();
+        first.push(&[cname("www.example.com", "cdn.example.net")]);
+        let mut second = ResolutionTable::default();
+        second.push(&[addr("cdn.example.net", "2001:db8::1")]);
+
+        first.merge(second);
+        assert_eq!(chains(&first, "2001:db8::1"), ["www.example.com -> cdn.example.net"]);
+    }
+}
@@ -0,0 +1,154 @@
+use crate::stats::FlowStat;
+use publicsuffix::{IcannList, Psl};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::net::IpAddr;
+use std::sync::OnceLock;
+
+// 内置的公共后缀列表（https://publicsuffix.org/list/），可通过 PSL_PATH 指定更新的版本
+const BUNDLED_PSL: &str = include_str!("../data/public_suffix_list.dat");
+
+// 无法确定域名的 IP 归入该分组
+pub const UNATTRIBUTED: &str = "(未识别)";
+
+static PSL: OnceLock<IcannList> = OnceLock::new();
+
+/// 按域名汇总的流量
+#[derive(Debug, Default, Clone)]
+pub struct DomainStat {
+    pub ips: Vec<IpAddr>,
+    pub total_pkts: u64,
+    pub total_bytes: u64,
+    pub up_pkts: u64,
+    pub up_bytes: u64,
+    pub down_pkts: u64,
+    pub down_bytes: u64,
+}
+
+impl DomainStat {
+    fn add(&mut self, ip: IpAddr, stat: &FlowStat) {
+        self.ips.push(ip);
+        self.total_pkts += stat.total_pkts;
+        self.total_bytes += stat.total_bytes;
+        self.up_pkts += stat.up_pkts;
+        self.up_bytes += stat.up_bytes;
+        self.down_pkts += stat.down_pkts;
+        self.down_bytes += stat.down_bytes;
+    }
+}
+
+/// 确定 IP 归属的唯一域名，保证按域名汇总时每个 IP 只计一次：
+/// 1. 数据包中直接出现的 HTTP Host / TLS SNI，取出现次数最多的一个；
+/// 2. 没有时取 DNS 应答中解析到该 IP 的查询域名，按字母序取第一个；
+/// 3. 都没有时返回 None，归入“(未识别)”。
+///
+/// DNS 查询名只说明该 IP 是 DNS 服务器，不参与归属。
+pub fn primary_domain(stat: &FlowStat) -> Option<&str> {
+    let observed = stat
+        .hosts
+        .iter()
+        .max_by(|a, b| a.1.cmp(b.1).then_with(|| b.0.cmp(a.0)))
+        .map(|(host, _)| host.as_str());
+    observed.or_else(|| stat.resolved_names.iter().next().map(|name| name.as_str()))
+}
+
+/// 可注册域名（eTLD+1），如 "a.b.example.co.uk" 为 "example.co.uk"；本身就是公共后缀时返回原域名
+pub fn registrable_domain(name: &str) -> String {
+    psl()
+        .domain(name.as_bytes())
+        .map(|domain| String::from_utf8_lossy(domain.as_bytes()).into_owned())
+        .unwrap_or_else(|| name.to_string())
+}
+
+/// 按归属域名汇总，registrable 为 true 时按可注册域名汇总；结果按总数据量降序
+pub fn aggregate_by_domain(stats_map: &HashMap<IpAddr, FlowStat>, registrable: bool) -> Vec<(String, DomainStat)> {
+    let mut domains: HashMap<String, DomainStat> = HashMap::new();
+    for (ip, stat) in stats_map {
+        let key = match primary_domain(stat) {
+            Some(domain) if registrable => registrable_domain(domain),
+            Some(domain) => domain.to_string(),
+            None => UNATTRIBUTED.to_string(),
+        };
+        domains.entry(key).or_default().add(*ip, stat);
+    }
+
+    let mut sorted: Vec<(String, DomainStat)> = domains.into_iter().collect();
+    for (_, domain) in sorted.iter_mut() {
+        domain.ips.sort();
+    }
+    sorted.sort_by(|a, b| b.1.total_bytes.cmp(&a.1.total_bytes).then_with(|| a.0.cmp(&b.0)));
+    sorted
+}
+
+fn psl() -> &'static IcannList {
+    PSL.get_or_init(|| {
+        if let Ok(path) = env::var("PSL_PATH") {
+            let list = fs::read_to_string(path.trim())
+                .map_err(|e| e.to_string())
+                .and_then(|text| text.parse::<IcannList>().map_err(|e| e.to_string()));
+            match list {
+                Ok(list) => return list,
+                Err(e) => log::warn!("无法加载公共后缀列表 {}: {}，使用内置列表", path, e),
+            }
+        }
+        BUNDLED_PSL.parse().expect("内置公共后缀列表格式错误")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stat(hosts: &[(&str, u64)], resolved: &[&str], bytes: u64) -> FlowStat {
+        let mut stat = FlowStat { total_pkts: 1, total_bytes: bytes, ..Default::default() };
+        stat.hosts = hosts.iter().map(|(host, count)| (host.to_string(), *count)).collect();
+        stat.resolved_names = resolved.iter().map(|name| name.to_string()).collect();
+        stat
+    }
+
+    #[test]
+    fn primary_domain_prefers_observed_hosts() {
+        // 出现次数最多的 Host/SNI，次数相同时取字母序靠前的
+        assert_eq!(primary_domain(&stat(&[("b.example.com", 3), ("a.example.com", 1)], &["dns.example.com"], 0)), Some("b.example.com"));
+        assert_eq!(primary_domain(&stat(&[("b.example.com", 2), ("a.example.com", 2)], &[], 0)), Some("a.example.com"));
+        // 没有时取 DNS 解析到该 IP 的域名
+        assert_eq!(primary_domain(&stat(&[], &["z.example.com", "m.example.com"], 0)), Some("m.example.com"));
+        // 查询名不参与归属
+        let mut query_only = stat(&[], &[], 0);
+        query_only.domains.insert("query.example.com".to_string());
+        assert_eq!(primary_domain(&query_only), None);
+    }
+
+    #[test]
+    fn registrable_domain_uses_public_suffixes() {
+        assert_eq!(registrable_domain("a.b.example.co.uk"), "example.co.uk");
+        assert_eq!(registrable_domain("www.example.com"), "example.com");
+        assert_eq!(registrable_domain("example.com"), "example.com");
+        // 本身就是公共后缀
+        assert_eq!(registrable_domain("co.uk"), "co.uk");
+    }
+
+    #[test]
+    fn aggregate_by_domain_counts_each_ip_once() {
+        let stats: HashMap<IpAddr, FlowStat> = [
+            ("1.1.1.1", stat(&[("www.example.com", 1)], &["other.example.net"], 300)),
+            ("2.2.2.2", stat(&[], &["www.example.com"], 200)),
+            ("3.3.3.3", stat(&[("img.example.com", 1)], &[], 100)),
+            ("4.4.4.4", stat(&[], &[], 1000)),
+        ]
+        .into_iter()
+        .map(|(ip, stat)| (ip.parse().unwrap(), stat))
+        .collect();
+
+        let by_domain = aggregate_by_domain(&stats, false);
+        let summary: Vec<(&str, usize, u64)> =
+            by_domain.iter().map(|(domain, stat)| (domain.as_str(), stat.ips.len(), stat.total_bytes)).collect();
+        assert_eq!(summary, [(UNATTRIBUTED, 1, 1000), ("www.example.com", 2, 500), ("img.example.com", 1, 100)]);
+        assert_eq!(by_domain[1].1.ips, ["1.1.1.1".parse::<IpAddr>().unwrap(), "2.2.2.2".parse().unwrap()]);
+
+        let by_registrable = aggregate_by_domain(&stats, true);
+        let summary: Vec<(&str, u64)> = by_registrable.iter().map(|(domain, stat)| (domain.as_str(), stat.total_bytes)).collect();
+        assert_eq!(summary, [(UNATTRIBUTED, 1000), ("example.com", 600)]);
+    }
+}
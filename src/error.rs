@@ -0,0 +1,71 @@
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// 分析过程中的错误。错误信息为纯文本，提示符号与命令行参数的说明由调用方添加
+#[derive(Debug, Error)]
+pub enum Error {
+    /// 文件读写失败
+    #[error("文件读写失败: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// CSV 写入失败
+    #[error("CSV 写入失败: {0}")]
+    Csv(#[from] csv::Error),
+
+    /// JSON 写入失败
+    #[error("JSON 写入失败: {0}")]
+    Json(#[from] serde_json::Error),
+
+    /// Excel 写入失败
+    #[error("Excel 写入失败: {0}")]
+    Xlsx(#[from] rust_xlsxwriter::XlsxError),
+
+    /// HTTP 客户端初始化失败
+    #[error("HTTP 客户端初始化失败: {0}")]
+    Http(#[from] reqwest::Error),
+
+    /// 抓包文件格式无法识别或已损坏
+    #[error("{0}")]
+    Capture(String),
+
+    /// tshark 执行失败
+    #[error("{0}")]
+    Tshark(String),
+
+    /// .env 或分析选项中的配置有误
+    #[error("{0}")]
+    Config(String),
+
+    /// 无法自动确定本机地址
+    #[error("{0}")]
+    LocalHostNotFound(String),
+
+    #[error("无效的 IP 地址: {0}")]
+    InvalidIp(String),
+
+    #[error("非公网 IP 地址: {0}")]
+    NotPublicIp(std::net::IpAddr),
+
+    /// 多主机模式下没有找到有外部流量的内网主机
+    #[error("未找到有外部流量的内网主机")]
+    NoInternalHosts,
+
+    #[error("输入路径不存在: {}", .0.display())]
+    NotFound(PathBuf),
+
+    #[error("{} 不是一个目录", .0.display())]
+    NotADirectory(PathBuf),
+
+    #[error("目录 {} 中没有找到 .pcap 或 .pcapng 文件", .0.display())]
+    NoCaptureFiles(PathBuf),
+
+    /// 目录中的某个文件分析失败
+    #[error("{}: {source}", path.display())]
+    File {
+        path: PathBuf,
+        #[source]
+        source: Box<Error>,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
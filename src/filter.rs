@@ -0,0 +1,278 @@
+use crate::error::Error;
+use crate::location::GeoLocation;
+use crate::stats::FlowStat;
+use ipnet::IpNet;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::net::IpAddr;
+
+/// 单条过滤规则
+#[derive(Debug, Clone)]
+enum Matcher {
+    /// 域名包含关键字（不区分大小写）
+    Keyword(String),
+    /// 域名匹配正则表达式，或由通配符（如 *.example.com）转换而来的整名匹配
+    Pattern(Regex),
+    /// IP 位于网段内
+    Net(IpNet),
+    /// 归属地查询得到的 ASN
+    Asn(u32),
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    // 规则原文，写入“匹配规则”列
+    text: String,
+    matcher: Matcher,
+}
+
+impl Rule {
+    /// 解析一条规则：re:<正则>、AS<编号>、IP 或 CIDR、含 * ? 的通配符，其余为关键字
+    fn parse(text: &str) -> Result<Self, String> {
+        let matcher = if let Some(pattern) = text.strip_prefix("re:") {
+            let regex = Regex::new(&format!("(?i){}", pattern))
+                .map_err(|e| format!("无效的正则表达式 {}: {}", pattern, e))?;
+            Matcher::Pattern(regex)
+        } else if let Some(asn) = parse_asn(text) {
+            Matcher::Asn(asn)
+        } else if let Ok(net) = text.parse::<IpNet>() {
+            Matcher::Net(net)
+        } else if let Ok(ip) = text.parse::<IpAddr>() {
+            Matcher::Net(IpNet::from(ip))
+        } else if text.contains(['*', '?']) {
+            Matcher::Pattern(wildcard_regex(text))
+        } else {
+            Matcher::Keyword(text.to_lowercase())
+        };
+        Ok(Rule { text: text.to_string(), matcher })
+    }
+
+    fn matches(&self, ip: &IpAddr, names: &[&str], location: Option<&GeoLocation>) -> bool {
+        match &self.matcher {
+            Matcher::Keyword(keyword) => names.iter().any(|name| name.to_lowercase().contains(keyword)),
+            Matcher::Pattern(regex) => names.iter().any(|name| regex.is_match(name)),
+            Matcher::Net(net) => net.contains(ip),
+            Matcher::Asn(asn) => location.and_then(|loc| loc.asn) == Some(*asn),
+        }
+    }
+}
+
+/// 结果过滤器：保留匹配任一包含规则的 IP，再去掉匹配任一排除规则的 IP
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    include: Vec<Rule>,
+    exclude: Vec<Rule>,
+}
+
+impl Filter {
+    /// 添加包含规则，spec 为逗号分隔的规则，@<文件> 表示从列表文件读取
+    pub fn include(&mut self, spec: &str) -> crate::Result<()> {
+        self.include.extend(parse_rules(spec).map_err(Error::Config)?);
+        Ok(())
+    }
+
+    /// 添加排除规则，格式同 include
+    pub fn exclude(&mut self, spec: &str) -> crate::Result<()> {
+        self.exclude.extend(parse_rules(spec).map_err(Error::Config)?);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// 是否有按 ASN 匹配的规则，需要先查询归属地再过滤
+    pub fn needs_location(&self) -> bool {
+        self.include.iter().chain(&self.exclude).any(|rule| matches!(rule.matcher, Matcher::Asn(_)))
+    }
+
+    /// 过滤统计表，并在保留的行中记录匹配到的包含规则
+    pub fn apply(&self, stats: &mut HashMap<IpAddr, FlowStat>, locations: Option<&HashMap<IpAddr, GeoLocation>>) {
+        if self.is_empty() {
+            return;
+        }
+        stats.retain(|ip, stat| {
+            let names = stat_names(stat);
+            let location = locations.and_then(|locs| locs.get(ip));
+            if self.exclude.iter().any(|rule| rule.matches(ip, &names, location)) {
+                return false;
+            }
+            if self.include.is_empty() {
+                return true;
+            }
+            match self.include.iter().find(|rule| rule.matches(ip, &names, location)) {
+                Some(rule) => {
+                    stat.matched_rule = rule.text.clone();
+                    true
+                }
+                None => false,
+            }
+        });
+    }
+}
+
+// 参与匹配的域名：数据包中出现的域名、DNS 解析到该 IP 的域名及 CNAME 链上的各级域名
+fn stat_names(stat: &FlowStat) -> Vec<&str> {
+    let mut names: Vec<&str> = stat.domains.iter().map(|d| d.as_str()).collect();
+    names.extend(stat.resolved_names.iter().map(|d| d.as_str()));
+    names.extend(stat.cname_chains.iter().flat_map(|chain| chain.split(" -> ")));
+    names
+}
+
+// 规则以逗号分隔；正则中常有逗号（如 \d{1,3}），因此 re: 之后的内容整体作为一条正则，
+// 多条正则需重复参数或写在列表文件中
+fn parse_rules(spec: &str) -> Result<Vec<Rule>, String> {
+    let mut rules = Vec::new();
+    let mut rest = spec.trim_start();
+    while !rest.is_empty() {
+        let (item, next) = match rest.split_once(',') {
+            Some((item, next)) if !rest.starts_with("re:") => (item, next),
+            _ => (rest, ""),
+        };
+        rest = next.trim_start();
+
+        let item = item.trim();
+        if item.is_empty() {
+            continue;
+        }
+        match item.strip_prefix('@') {
+            Some(path) => rules.extend(load_list(path)?),
+            None => rules.push(Rule::parse(item)?),
+        }
+    }
+    Ok(rules)
+}
+
+// 列表文件每行一条规则，忽略空行和 # 开头的注释
+fn load_list(path: &str) -> Result<Vec<Rule>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("无法读取规则列表 {}: {}", path, e))?;
+    text.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(Rule::parse)
+        .collect()
+}
+
+// 形如 AS15169 的 ASN，不区分大小写
+fn parse_asn(text: &str) -> Option<u32> {
+    let digits = text.get(..2).filter(|p| p.eq_ignore_ascii_case("as")).and(text.get(2..))?;
+    digits.parse().ok()
+}
+
+// 通配符转为整名匹配的正则：* 匹配任意字符，? 匹配单个字符
+fn wildcard_regex(pattern: &str) -> Regex {
+    let escaped = regex::escape(pattern).replace(r"\*", ".*").replace(r"\?", ".");
+    Regex::new(&format!("(?i)^{}$", escaped)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kind(text: &str) -> String {
+        match Rule::parse(text).unwrap().matcher {
+            Matcher::Keyword(k) => format!("keyword:{}", k),
+            Matcher::Pattern(r) => format!("pattern:{}", r.as_str()),
+            Matcher::Net(n) => format!("net:{}", n),
+            Matcher::Asn(a) => format!("asn:{}", a),
+        }
+    }
+
+    #[test]
+    fn rule_parse_precedence() {
+        // re: 优先，即使正则本身像 IP 或 ASN
+        assert_eq!(kind("re:^as\\d+$"), "pattern:(?i)^as\\d+$");
+        assert_eq!(kind("re:10.0.0.1"), "pattern:(?i)10.0.0.1");
+        assert_eq!(kind("AS15169"), "asn:15169");
+        assert_eq!(kind("as13335"), "asn:13335");
+        assert_eq!(kind("10.0.0.0/8"), "net:10.0.0.0/8");
+        assert_eq!(kind("2001:db8::/32"), "net:2001:db8::/32");
+        assert_eq!(kind("8.8.8.8"), "net:8.8.8.8/32");
+        assert_eq!(kind("*.Example.com"), "pattern:(?i)^.*\\.Example\\.com$");
+        // 不是编号的 AS 前缀按关键字处理
+        assert_eq!(kind("Asia"), "keyword:asia");
+        assert_eq!(kind("Google"), "keyword:google");
+        assert!(Rule::parse("re:(unclosed").is_err());
+    }
+
+    #[test]
+    fn wildcard_matches_whole_name() {
+        let regex = wildcard_regex("*.example.com");
+        assert!(regex.is_match("www.example.com"));
+        assert!(regex.is_match("A.B.EXAMPLE.COM"));
+        assert!(!regex.is_match("example.com"));
+        assert!(!regex.is_match("www.example.com.evil.net"));
+
+        let regex = wildcard_regex("cdn?.example.com");
+        assert!(regex.is_match("cdn1.example.com"));
+        assert!(!regex.is_match("cdn12.example.com"));
+        // 点按字面匹配
+        assert!(!regex.is_match("cdn1xexample.com"));
+    }
+
+    #[test]
+    fn parse_rules_keeps_commas_in_regex() {
+        let rules = parse_rules(r" google, *.qq.com ,re:^cdn\d{1,3}\.example\.com$").unwrap();
+        let texts: Vec<&str> = rules.iter().map(|r| r.text.as_str()).collect();
+        assert_eq!(texts, ["google", "*.qq.com", r"re:^cdn\d{1,3}\.example\.com$"]);
+        assert!(rules[2].matches(&"1.1.1.1".parse().unwrap(), &["cdn12.example.com"], None));
+        assert!(!rules[2].matches(&"1.1.1.1".parse().unwrap(), &["cdn1234.example.com"], None));
+
+        assert!(parse_rules(" , ,").unwrap().is_empty());
+    }
+
+    #[test]
+    fn filter_apply_excludes_then_includes() {
+        let mut stats: HashMap<IpAddr, FlowStat> = HashMap::new();
+        for (ip, domain) in [("1.1.1.1", "a.example.com"), ("2.2.2.2", "b.example.com"), ("3.3.3.3", "other.net")] {
+            let mut stat = FlowStat::default();
+            stat.domains.insert(domain.to_string());
+            stats.insert(ip.parse().unwrap(), stat);
+        }
+
+        let mut filter = Filter::default();
+        filter.include("example").unwrap();
+        filter.exclude("2.2.2.2").unwrap();
+        assert!(!filter.needs_location());
+        filter.apply(&mut stats, None);
+
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[&"1.1.1.1".parse::<IpAddr>().unwrap()].matched_rule, "example");
+    }
+
+    #[test]
+    fn asn_rules_and_rule_lists() {
+        let path = std::env::temp_dir().join(format!("pcapracer-{}-rules.txt", std::process::id()));
+        fs::write(&path, "# 注释\n\nAS15169\n*.example.com\n").unwrap();
+        let rules = parse_rules(&format!("keyword,@{}", path.display()));
+        let _ = fs::remove_file(&path);
+        let texts: Vec<String> = rules.unwrap().into_iter().map(|r| r.text).collect();
+        assert_eq!(texts, ["keyword", "AS15169", "*.example.com"]);
+        assert!(parse_rules("@/nonexistent/rules.txt").is_err());
+
+        let rule = Rule::parse("AS15169").unwrap();
+        let ip: IpAddr = "8.8.8.8".parse().unwrap();
+        let location = GeoLocation { asn: Some(15169), ..Default::default() };
+        assert!(rule.matches(&ip, &[], Some(&location)));
+        // 没有归属地时 ASN 规则不匹配
+        assert!(!rule.matches(&ip, &[], None));
+
+        let mut filter = Filter::default();
+        filter.exclude("as15169").unwrap();
+        assert!(filter.needs_location());
+    }
+
+    #[test]
+    fn rules_match_resolved_names_and_cname_chains() {
+        let mut stat = FlowStat::default();
+        stat.resolved_names.insert("www.example.com".to_string());
+        stat.cname_chains.insert("www.example.com -> edge.cdn.net".to_string());
+        let names = stat_names(&stat);
+        let ip: IpAddr = "1.1.1.1".parse().unwrap();
+
+        assert!(Rule::parse("*.cdn.net").unwrap().matches(&ip, &names, None));
+        assert!(Rule::parse("EXAMPLE").unwrap().matches(&ip, &names, None));
+        assert!(!Rule::parse("other").unwrap().matches(&ip, &names, None));
+    }
+}
@@ -0,0 +1,182 @@
+use crate::packet::PacketRecord;
+use chrono::{Local, TimeZone};
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+// TCP 标志位
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+
+/// 会话五元组，方向以会话中第一个数据包为准（源即发起方）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FlowKey {
+    pub src: IpAddr,
+    pub src_port: u16,
+    pub dst: IpAddr,
+    pub dst_port: u16,
+    pub proto: u8,
+}
+
+impl FlowKey {
+    fn reversed(&self) -> Self {
+        FlowKey {
+            src: self.dst,
+            src_port: self.dst_port,
+            dst: self.src,
+            dst_port: self.src_port,
+            proto: self.proto,
+        }
+    }
+}
+
+/// 单个会话的统计，正向为源到目的，反向为目的到源
+#[derive(Debug, Clone, Default)]
+pub struct FlowEntry {
+    pub first_ts: f64,
+    pub last_ts: f64,
+    pub fwd_pkts: u64,
+    pub fwd_bytes: u64,
+    pub rev_pkts: u64,
+    pub rev_bytes: u64,
+    /// 出现过的 TCP 标志位（按位或）
+    pub tcp_flags: u8,
+}
+
+impl FlowEntry {
+    pub fn duration(&self) -> f64 {
+        (self.last_ts - self.first_ts).max(0.0)
+    }
+
+    fn observe_time(&mut self, ts: f64) {
+        // 没有时间戳的数据包不参与起止时间
+        if ts <= 0.0 {
+            return;
+        }
+        if self.first_ts == 0.0 || ts < self.first_ts {
+            self.first_ts = ts;
+        }
+        if ts > self.last_ts {
+            self.last_ts = ts;
+        }
+    }
+
+    fn merge(&mut self, other: &FlowEntry, reversed: bool) {
+        if other.first_ts > 0.0 {
+            self.observe_time(other.first_ts);
+            self.observe_time(other.last_ts);
+        }
+        if reversed {
+            self.fwd_pkts += other.rev_pkts;
+            self.fwd_bytes += other.rev_bytes;
+            self.rev_pkts += other.fwd_pkts;
+            self.rev_bytes += other.fwd_bytes;
+        } else {
+            self.fwd_pkts += other.fwd_pkts;
+            self.fwd_bytes += other.fwd_bytes;
+            self.rev_pkts += other.rev_pkts;
+            self.rev_bytes += other.rev_bytes;
+        }
+        self.tcp_flags |= other.tcp_flags;
+    }
+}
+
+/// 按五元组聚合的会话表，记录本机与远端之间计入统计的 IP 会话
+#[derive(Debug, Default)]
+pub struct FlowTable {
+    flows: HashMap<FlowKey, FlowEntry>,
+}
+
+impl FlowTable {
+    pub fn push(&mut self, record: &PacketRecord) {
+        let (Some(src), Some(dst)) = (record.src, record.dst) else {
+            return;
+        };
+        let key = FlowKey {
+            src,
+            src_port: record.src_port,
+            dst,
+            dst_port: record.dst_port,
+            proto: record.proto,
+        };
+
+        // 先按正向查找，再按反向查找，都没有则以当前数据包方向新建
+        let reversed = !self.flows.contains_key(&key) && self.flows.contains_key(&key.reversed());
+        let entry = if reversed {
+            self.flows.get_mut(&key.reversed()).unwrap()
+        } else {
+            self.flows.entry(key).or_default()
+        };
+
+        if reversed {
+            entry.rev_pkts += 1;
+            entry.rev_bytes += record.len;
+        } else {
+            entry.fwd_pkts += 1;
+            entry.fwd_bytes += record.len;
+        }
+        entry.tcp_flags |= record.tcp_flags;
+        entry.observe_time(record.timestamp);
+    }
+
+    /// 合并另一份会话表，同一会话在两份表中方向相反时按已有方向合并
+    pub fn merge(&mut self, other: FlowTable) {
+        for (key, entry) in other.flows {
+            if let Some(existing) = self.flows.get_mut(&key) {
+                existing.merge(&entry, false);
+            } else if let Some(existing) = self.flows.get_mut(&key.reversed()) {
+                existing.merge(&entry, true);
+            } else {
+                self.flows.insert(key, entry);
+            }
+        }
+    }
+
+    /// 按开始时间排序的会话列表
+    pub fn sorted(&self) -> Vec<(&FlowKey, &FlowEntry)> {
+        let mut flows: Vec<(&FlowKey, &FlowEntry)> = self.flows.iter().collect();
+        flows.sort_by(|a, b| {
+            a.1.first_ts
+                .total_cmp(&b.1.first_ts)
+                .then_with(|| b.1.fwd_bytes.cmp(&a.1.fwd_bytes))
+                .then_with(|| a.0.cmp(b.0))
+        });
+        flows
+    }
+}
+
+/// TCP 标志汇总，如 "SYN,FIN"
+pub fn format_tcp_flags(flags: u8) -> String {
+    let mut names = Vec::new();
+    if flags & TCP_SYN != 0 {
+        names.push("SYN");
+    }
+    if flags & TCP_FIN != 0 {
+        names.push("FIN");
+    }
+    if flags & TCP_RST != 0 {
+        names.push("RST");
+    }
+    names.join(",")
+}
+
+/// 按本地时区格式化 Unix 时间戳，精确到微秒；没有时间戳时为空
+pub fn format_timestamp(ts: f64) -> String {
+    if ts <= 0.0 {
+        return String::new();
+    }
+    let secs = ts.floor();
+    let nanos = ((ts - secs) * 1e9).round().min(999_999_999.0) as u32;
+    match Local.timestamp_opt(secs as i64, nanos) {
+        chrono::LocalResult::Single(time) => time.format("%Y-%m-%d %H:%M:%S%.6f").to_string(),
+        _ => String::new(),
+    }
+}
+
+/// 按本地时区格式化整秒的 Unix 时间戳，用于时间段起点
+pub fn format_timestamp_secs(secs: i64) -> String {
+    match Local.timestamp_opt(secs, 0) {
+        chrono::LocalResult::Single(time) => time.format("%Y-%m-%d %H:%M:%S").to_string(),
+        _ => String::new(),
+    }
+}
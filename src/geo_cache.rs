@@ -0,0 +1,142 @@
+use crate::location::GeoLocation;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// 默认缓存有效期：7 天
+const DEFAULT_TTL_HOURS: u64 = 24 * 7;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    location: GeoLocation,
+    // 写入时间（Unix 秒）
+    updated_at: u64,
+}
+
+/// 归属地查询结果的磁盘缓存（JSON），默认保存在可执行文件同目录的 geo_cache.json。
+/// 条目按 "查询来源|IP" 存放，不同来源的结果互不混用
+pub struct GeoCache {
+    path: PathBuf,
+    source: String,
+    ttl_secs: u64,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl GeoCache {
+    /// 读取 GEO_CACHE_PATH 与 GEO_CACHE_TTL_HOURS 配置，TTL 为 0 时不启用缓存。
+    /// source 为提供方的 [`GeoProvider::source`](crate::geo_provider::GeoProvider::source)
+    pub fn from_env(source: &str) -> Option<Self> {
+        let ttl_hours = match env::var("GEO_CACHE_TTL_HOURS") {
+            Ok(v) => match v.trim().parse::<u64>() {
+                Ok(hours) => hours,
+                Err(_) => {
+                    log::warn!("GEO_CACHE_TTL_HOURS 不是有效的整数: {}，使用默认值 {}", v, DEFAULT_TTL_HOURS);
+                    DEFAULT_TTL_HOURS
+                }
+            },
+            Err(_) => DEFAULT_TTL_HOURS,
+        };
+        if ttl_hours == 0 {
+            return None;
+        }
+
+        let path = match env::var("GEO_CACHE_PATH") {
+            Ok(p) if !p.trim().is_empty() => PathBuf::from(p.trim()),
+            _ => env::current_exe()
+                .ok()
+                .and_then(|exe| exe.parent().map(|dir| dir.join("geo_cache.json")))
+                .unwrap_or_else(|| PathBuf::from("geo_cache.json")),
+        };
+
+        // 缓存文件损坏时不影响查询，重新开始累积；无法识别的条目（如旧版的字符串归属地、
+        // 不带查询来源的键）直接丢弃
+        let entries = fs::read_to_string(&path)
+            .ok()
+            .and_then(|text| serde_json::from_str::<HashMap<String, serde_json::Value>>(&text).ok())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(key, _)| key.contains('|'))
+            .filter_map(|(key, value)| Some((key, serde_json::from_value(value).ok()?)))
+            .collect();
+
+        Some(GeoCache {
+            path,
+            source: source.to_string(),
+            ttl_secs: ttl_hours.saturating_mul(3600),
+            entries: Mutex::new(entries),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        })
+    }
+
+    /// 从缓存中取出未过期的结果，返回 (命中结果, 需要查询的 IP)
+    pub fn lookup(&self, ip_list: &[IpAddr]) -> (HashMap<IpAddr, GeoLocation>, Vec<IpAddr>) {
+        let now = now_secs();
+        let entries = self.entries.lock().unwrap();
+
+        let mut found = HashMap::new();
+        let mut missing = Vec::new();
+        for ip in ip_list {
+            match entries.get(&self.key(ip)) {
+                Some(entry) if now.saturating_sub(entry.updated_at) < self.ttl_secs => {
+                    found.insert(*ip, entry.location.clone());
+                }
+                _ => missing.push(*ip),
+            }
+        }
+
+        self.hits.fetch_add(found.len() as u64, Ordering::Relaxed);
+        self.misses.fetch_add(missing.len() as u64, Ordering::Relaxed);
+        (found, missing)
+    }
+
+    /// 写入成功查询到的结果并落盘
+    pub fn store(&self, results: &HashMap<IpAddr, GeoLocation>) {
+        if results.is_empty() {
+            return;
+        }
+
+        let now = now_secs();
+        let mut entries = self.entries.lock().unwrap();
+        for (ip, location) in results {
+            entries.insert(
+                self.key(ip),
+                CacheEntry { location: location.clone(), updated_at: now },
+            );
+        }
+
+        // 先写临时文件再替换，避免中途退出导致缓存文件损坏
+        let tmp_path = self.path.with_extension("json.tmp");
+        let result = serde_json::to_string(&*entries)
+            .map_err(|e| e.to_string())
+            .and_then(|text| fs::write(&tmp_path, text).map_err(|e| e.to_string()))
+            .and_then(|_| fs::rename(&tmp_path, &self.path).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            log::warn!("归属地缓存写入失败 {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// (命中数, 未命中数)
+    pub fn counts(&self) -> (u64, u64) {
+        (self.hits.load(Ordering::Relaxed), self.misses.load(Ordering::Relaxed))
+    }
+
+    fn key(&self, ip: &IpAddr) -> String {
+        format!("{}|{}", self.source, ip)
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,334 @@
+use crate::error::{Error, Result};
+use crate::location::GeoLocation;
+use maxminddb::{geoip2, Reader};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::Path;
+
+// IP2Location BIN 各字段所在列，下标为数据库类型 DB1~DB26，0 表示该类型无此字段
+const IP2L_COUNTRY_POSITION: [u8; 27] = [0, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2];
+const IP2L_REGION_POSITION: [u8; 27] = [0, 0, 0, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3];
+const IP2L_CITY_POSITION: [u8; 27] = [0, 0, 0, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4];
+const IP2L_ISP_POSITION: [u8; 27] = [0, 0, 3, 0, 5, 0, 7, 5, 7, 0, 8, 0, 9, 0, 9, 0, 9, 0, 9, 7, 9, 0, 9, 7, 9, 9, 9];
+
+/// 离线归属地库：MaxMind MMDB（GeoLite2 City/Country/ASN）或 IP2Location BIN
+pub enum OfflineDb {
+    MaxMind {
+        city: Option<Reader<Vec<u8>>>,
+        asn: Option<Reader<Vec<u8>>>,
+    },
+    Ip2Location(Ip2LocationDb),
+}
+
+impl OfflineDb {
+    /// 打开离线库。MMDB 可同时给出城市库和 ASN 库，按库的元数据自动区分；
+    /// IP2Location BIN 库只能单独使用，与其他库同时给出时返回配置错误
+    pub fn open(paths: &[&str]) -> Result<Self> {
+        let is_bin = |path: &str| Path::new(path).extension().is_some_and(|ext| ext.eq_ignore_ascii_case("bin"));
+        match paths.iter().filter(|path| is_bin(path)).count() {
+            0 => {}
+            1 if paths.len() == 1 => return Ok(OfflineDb::Ip2Location(Ip2LocationDb::open(paths[0])?)),
+            _ => {
+                return Err(Error::Config(format!(
+                    "IP2Location BIN 库只能单独使用，不能与其他库同时设置: {}",
+                    paths.join(",")
+                )))
+            }
+        }
+
+        let mut city = None;
+        let mut asn = None;
+
+        for path in paths {
+            let reader = Reader::open_readfile(path)
+                .map_err(|e| Error::Config(format!("无法打开离线归属地库 {}: {}", path, e)))?;
+            if reader.metadata.database_type.contains("ASN") {
+                asn = Some(reader);
+            } else {
+                city = Some(reader);
+            }
+        }
+
+        Ok(OfflineDb::MaxMind { city, asn })
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> Option<GeoLocation> {
+        match self {
+            OfflineDb::MaxMind { city, asn } => {
+                let mut loc = GeoLocation::default();
+                let mut found = false;
+
+                if let Some(record) = city.as_ref().and_then(|r| r.lookup::<geoip2::City>(ip).ok()) {
+                    found = true;
+                    loc.country = record.country.and_then(|c| c.names).map(pick_name).unwrap_or_default();
+                    loc.province = record
+                        .subdivisions
+                        .and_then(|subs| subs.into_iter().next())
+                        .and_then(|s| s.names)
+                        .map(pick_name)
+                        .unwrap_or_default();
+                    loc.city = record.city.and_then(|c| c.names).map(pick_name).unwrap_or_default();
+                }
+
+                if let Some(record) = asn.as_ref().and_then(|r| r.lookup::<geoip2::Asn>(ip).ok()) {
+                    found = true;
+                    loc.asn = record.autonomous_system_number;
+                    loc.org = record.autonomous_system_organization.unwrap_or_default().to_string();
+                }
+
+                found.then_some(loc)
+            }
+            OfflineDb::Ip2Location(db) => db.lookup(ip),
+        }
+    }
+}
+
+// 优先使用中文名称，没有则用英文
+fn pick_name(names: BTreeMap<&str, &str>) -> String {
+    names
+        .get("zh-CN")
+        .or_else(|| names.get("en"))
+        .map(|s| s.to_string())
+        .unwrap_or_default()
+}
+
+/// IP2Location BIN 格式数据库，整体读入内存后二分查找
+pub struct Ip2LocationDb {
+    data: Vec<u8>,
+    db_type: usize,
+    db_column: usize,
+    ipv4_count: usize,
+    ipv4_addr: usize,
+    ipv6_count: usize,
+    ipv6_addr: usize,
+}
+
+impl Ip2LocationDb {
+    pub fn open(path: &str) -> Result<Self> {
+        Self::from_bytes(std::fs::read(path)?, path)
+    }
+
+    // 解析已读入内存的库文件，path 仅用于错误信息
+    fn from_bytes(data: Vec<u8>, path: &str) -> Result<Self> {
+        if data.len() < 64 {
+            return Err(Error::Config(format!("IP2Location 库文件过小: {}", path)));
+        }
+
+        let db_type = data[0] as usize;
+        if db_type == 0 || db_type >= IP2L_COUNTRY_POSITION.len() {
+            return Err(Error::Config(format!("不支持的 IP2Location 库类型 DB{}: {}", db_type, path)));
+        }
+
+        let u32_at = |pos: usize| u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        let db = Ip2LocationDb {
+            db_type,
+            db_column: data[1] as usize,
+            ipv4_count: u32_at(5),
+            ipv4_addr: u32_at(9),
+            ipv6_count: u32_at(13),
+            ipv6_addr: u32_at(17),
+            data,
+        };
+        Ok(db)
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> Option<GeoLocation> {
+        // 库中的地址均为从 1 开始的偏移；IPv6 行比 IPv4 行多 12 字节（起始地址为 16 字节）
+        // 最后一行的结束地址即地址空间的最大值，查询最大地址时按其前一个地址查找
+        let (ip_num, count, base, extra) = match ip {
+            IpAddr::V4(v4) => (u32::from(v4).min(u32::MAX - 1) as u128, self.ipv4_count, self.ipv4_addr, 0),
+            IpAddr::V6(v6) => (u128::from(v6).min(u128::MAX - 1), self.ipv6_count, self.ipv6_addr, 12),
+        };
+        if count == 0 || base == 0 {
+            return None;
+        }
+
+        let row_size = self.db_column * 4 + extra;
+        let row_start = |row: usize| base - 1 + row * row_size;
+
+        let mut low = 0usize;
+        let mut high = count;
+        while low <= high {
+            let mid = (low + high) / 2;
+            let ip_from = self.read_ip(row_start(mid), extra)?;
+            let ip_to = self.read_ip(row_start(mid + 1), extra)?;
+
+            if ip_num >= ip_from && ip_num < ip_to {
+                let row = row_start(mid) + extra;
+                let field = |positions: &[u8; 27]| self.read_field(row, positions[self.db_type]);
+                let country = field(&IP2L_COUNTRY_POSITION)
+                    .map(|ptr| self.read_str(ptr + 3))
+                    .unwrap_or_default();
+
+                // 未收录的网段以 "-" 占位
+                if country.is_empty() || country == "-" {
+                    return None;
+                }
+                let isp = field(&IP2L_ISP_POSITION).map(|ptr| self.read_str(ptr)).unwrap_or_default();
+                return Some(GeoLocation {
+                    country,
+                    province: field(&IP2L_REGION_POSITION).map(|ptr| self.read_str(ptr)).unwrap_or_default(),
+                    city: field(&IP2L_CITY_POSITION).map(|ptr| self.read_str(ptr)).unwrap_or_default(),
+                    org: isp.clone(),
+                    isp,
+                    asn: None,
+                });
+            }
+
+            if ip_num < ip_from {
+                high = mid.checked_sub(1)?;
+            } else {
+                low = mid + 1;
+            }
+        }
+        None
+    }
+
+    fn read_ip(&self, pos: usize, extra: usize) -> Option<u128> {
+        if extra == 0 {
+            let b = self.data.get(pos..pos + 4)?;
+            Some(u32::from_le_bytes(b.try_into().ok()?) as u128)
+        } else {
+            let b = self.data.get(pos..pos + 16)?;
+            Some(u128::from_le_bytes(b.try_into().ok()?))
+        }
+    }
+
+    // 读取指定列中存放的字符串指针
+    fn read_field(&self, row: usize, position: u8) -> Option<usize> {
+        if position == 0 {
+            return None;
+        }
+        let pos = row + 4 * (position as usize - 1);
+        let b = self.data.get(pos..pos + 4)?;
+        Some(u32::from_le_bytes(b.try_into().ok()?) as usize)
+    }
+
+    // 字符串格式：1 字节长度 + 内容
+    fn read_str(&self, ptr: usize) -> String {
+        let Some(&len) = self.data.get(ptr) else {
+            return String::new();
+        };
+        self.data
+            .get(ptr + 1..ptr + 1 + len as usize)
+            .map(|s| String::from_utf8_lossy(s).into_owned())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 写入 “1 字节长度 + 内容” 格式的字符串，返回其偏移
+    fn push_str(data: &mut Vec<u8>, text: &str) -> u32 {
+        let ptr = data.len() as u32;
+        data.push(text.len() as u8);
+        data.extend_from_slice(text.as_bytes());
+        ptr
+    }
+
+    // 国家字段：短代码占 3 字节（不足补 0），之后是全称，指针指向短代码
+    fn push_country(data: &mut Vec<u8>, code: &str, name: &str) -> u32 {
+        let ptr = push_str(data, code);
+        data.resize(ptr as usize + 3, 0);
+        push_str(data, name);
+        ptr
+    }
+
+    // 构造最小的 DB3 库（起始地址、国家、省份、城市四列）：
+    // IPv4 1.0.0.0/24、255.0.0.0/8 与 IPv6 2001:db8:: 起的全部地址有记录，其余网段为 "-"
+    fn synthetic_db3() -> Vec<u8> {
+        let mut data = vec![0u8; 64];
+        data[0] = 3;
+        data[1] = 4;
+
+        // 字符串区放在两张表之后，先按相对偏移写入
+        let base = (64 + 5 * 16 + 3 * 28) as u32;
+        let mut strings = Vec::new();
+        let none = base + push_country(&mut strings, "-", "-");
+        let au = base + push_country(&mut strings, "AU", "Australia");
+        let jp = base + push_country(&mut strings, "JP", "Japan");
+        let qld = base + push_str(&mut strings, "Queensland");
+        let brisbane = base + push_str(&mut strings, "Brisbane");
+        let tokyo = base + push_str(&mut strings, "Tokyo");
+        let empty = base + push_str(&mut strings, "-");
+
+        // IPv4：4 行记录加 1 行结束标记
+        let v4_rows: [(u32, u32, u32, u32); 5] = [
+            (0, none, empty, empty),
+            (0x0100_0000, au, qld, brisbane),
+            (0x0100_0100, none, empty, empty),
+            (0xFF00_0000, jp, tokyo, tokyo),
+            (u32::MAX, none, empty, empty),
+        ];
+        // IPv6：2 行记录加 1 行结束标记，起始地址为 16 字节
+        let v6_rows: [(u128, u32, u32, u32); 3] = [
+            (0, none, empty, empty),
+            (u128::from("2001:db8::".parse::<std::net::Ipv6Addr>().unwrap()), jp, tokyo, tokyo),
+            (u128::MAX, none, empty, empty),
+        ];
+
+        let v4_addr = data.len() as u32 + 1;
+        for (from, country, region, city) in v4_rows {
+            for v in [from, country, region, city] {
+                data.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        let v6_addr = data.len() as u32 + 1;
+        for (from, country, region, city) in v6_rows {
+            data.extend_from_slice(&from.to_le_bytes());
+            for v in [country, region, city] {
+                data.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        assert_eq!(data.len(), base as usize);
+        data.extend(strings);
+
+        data[5..9].copy_from_slice(&4u32.to_le_bytes());
+        data[9..13].copy_from_slice(&v4_addr.to_le_bytes());
+        data[13..17].copy_from_slice(&2u32.to_le_bytes());
+        data[17..21].copy_from_slice(&v6_addr.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn ip2location_lookup() {
+        let db = Ip2LocationDb::from_bytes(synthetic_db3(), "test.bin").unwrap();
+
+        let loc = db.lookup("1.0.0.1".parse().unwrap()).unwrap();
+        assert_eq!((loc.country.as_str(), loc.province.as_str(), loc.city.as_str()), ("Australia", "Queensland", "Brisbane"));
+        // DB3 没有运营商与 ASN
+        assert_eq!((loc.isp.as_str(), loc.asn), ("", None));
+        assert!(db.lookup("1.0.0.255".parse().unwrap()).is_some());
+
+        // 未收录的网段
+        assert!(db.lookup("1.0.1.0".parse().unwrap()).is_none());
+        assert!(db.lookup("0.255.255.255".parse().unwrap()).is_none());
+        assert!(db.lookup("8.8.8.8".parse().unwrap()).is_none());
+
+        let loc = db.lookup("2001:db8::1".parse().unwrap()).unwrap();
+        assert_eq!((loc.country.as_str(), loc.city.as_str()), ("Japan", "Tokyo"));
+        assert!(db.lookup("::1".parse().unwrap()).is_none());
+
+        // 地址空间的最后一个地址属于最后一行记录
+        for last in ["255.255.255.255", "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff"] {
+            assert_eq!(db.lookup(last.parse().unwrap()).unwrap().country, "Japan", "{}", last);
+        }
+    }
+
+    #[test]
+    fn ip2location_rejects_invalid_files() {
+        assert!(matches!(Ip2LocationDb::from_bytes(vec![0; 10], "short.bin"), Err(Error::Config(_))));
+        let mut data = synthetic_db3();
+        data[0] = 99;
+        assert!(matches!(Ip2LocationDb::from_bytes(data, "bad.bin"), Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn bin_cannot_be_mixed_with_other_databases() {
+        for paths in [&["a.BIN", "b.mmdb"][..], &["a.mmdb", "b.bin"], &["a.bin", "b.bin"]] {
+            assert!(matches!(OfflineDb::open(paths), Err(Error::Config(_))), "{:?}", paths);
+        }
+    }
+}
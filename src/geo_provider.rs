@@ -0,0 +1,421 @@
+use crate::error::Error;
+use crate::geo_db::OfflineDb;
+use crate::location::{query_ip_batch, GeoLocation};
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Method, StatusCode};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// 内部接口每次请求的 IP 数
+const LEGACY_BATCH_SIZE: usize = 100;
+
+// 默认每秒请求数；http 来源每次请求查询一个 IP，即每秒查询一个 IP
+const DEFAULT_RPS: f64 = 1.0;
+
+/// 归属地查询提供方，按批次查询 IP，返回查到的 IP 及其归属地
+pub trait GeoProvider: Send + Sync {
+    /// 提供方名称，用于提示信息
+    fn name(&self) -> &str;
+
+    fn query(&self, ip_list: &[IpAddr]) -> HashMap<IpAddr, GeoLocation>;
+
+    /// 查询结果是否需要写入磁盘缓存（离线库本身就在本地，无需缓存）
+    fn cacheable(&self) -> bool {
+        true
+    }
+
+    /// 缓存中区分查询来源的标识，切换接口后不会沿用其他来源的结果
+    fn source(&self) -> String {
+        self.name().to_string()
+    }
+
+    /// 重试后仍查询失败的 IP 数
+    fn failed_count(&self) -> u64 {
+        0
+    }
+}
+
+/// 在线查询的重试、限速与并发配置，由 GEO_RETRIES / GEO_BACKOFF_MS / GEO_MAX_BACKOFF_MS / GEO_RPS / GEO_CONCURRENCY 设置
+pub struct RequestPolicy {
+    retries: u32,
+    backoff_ms: u64,
+    // 单次等待的上限，服务器返回的 Retry-After 也不超过该值
+    max_backoff: Duration,
+    // 相邻两次请求的最小间隔，None 表示不限速
+    interval: Option<Duration>,
+    next_slot: Mutex<Instant>,
+    concurrency: usize,
+    failed: AtomicU64,
+}
+
+impl RequestPolicy {
+    pub fn from_env() -> Self {
+        let rps: f64 = env_number("GEO_RPS", DEFAULT_RPS);
+        // 速率过小时换算出的间隔超出 Duration 的范围
+        let interval = (rps > 0.0).then(|| {
+            Duration::try_from_secs_f64(1.0 / rps).unwrap_or_else(|_| {
+                log::warn!("GEO_RPS 过小: {:?}，使用默认值 {}", rps, DEFAULT_RPS);
+                Duration::from_secs_f64(1.0 / DEFAULT_RPS)
+            })
+        });
+        RequestPolicy {
+            retries: env_number("GEO_RETRIES", 3),
+            backoff_ms: env_number("GEO_BACKOFF_MS", 500),
+            max_backoff: Duration::from_millis(env_number("GEO_MAX_BACKOFF_MS", 30_000)),
+            interval,
+            next_slot: Mutex::new(Instant::now()),
+            concurrency: env_number::<usize>("GEO_CONCURRENCY", 1).max(1),
+            failed: AtomicU64::new(0),
+        }
+    }
+
+    // 按每秒请求数限速：预约下一个可用时间点，再在锁外等待
+    fn wait_turn(&self) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+        let wait = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let now = Instant::now();
+            let slot = (*next_slot).max(now);
+            *next_slot = slot + interval;
+            slot - now
+        };
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+
+    /// 发送请求，超时、连接失败、429 和 5xx 按指数退避重试，响应带 Retry-After 时按其等待；
+    /// 每次等待不超过 max_backoff
+    pub fn send<F>(&self, build: F) -> Result<Response, String>
+    where
+        F: Fn() -> RequestBuilder,
+    {
+        let mut attempt = 0;
+        loop {
+            self.wait_turn();
+            let (error, retry_after) = match build().send() {
+                Ok(resp) if resp.status().is_success() => return Ok(resp),
+                Ok(resp) => {
+                    let status = resp.status();
+                    if status != StatusCode::TOO_MANY_REQUESTS && !status.is_server_error() {
+                        return Err(format!("HTTP错误，状态码: {}", status));
+                    }
+                    (format!("HTTP错误，状态码: {}", status), retry_after(&resp))
+                }
+                Err(e) if e.is_timeout() || e.is_connect() => (format!("请求失败: {}", e), None),
+                Err(e) => return Err(format!("请求失败: {}", e)),
+            };
+
+            if attempt >= self.retries {
+                return Err(format!("{}（已重试 {} 次）", error, attempt));
+            }
+            let backoff = Duration::from_millis(self.backoff_ms.saturating_mul(1 << attempt.min(16)));
+            thread::sleep(retry_after.unwrap_or(backoff).min(self.max_backoff));
+            attempt += 1;
+        }
+    }
+
+    /// 将 IP 列表按块查询，最多 concurrency 个块同时进行；失败的块计入失败 IP 数
+    fn run_chunks<F>(&self, ip_list: &[IpAddr], chunk_size: usize, query: F) -> HashMap<IpAddr, GeoLocation>
+    where
+        F: Fn(&[IpAddr]) -> Result<HashMap<IpAddr, GeoLocation>, String> + Sync,
+    {
+        let chunks: Vec<&[IpAddr]> = ip_list.chunks(chunk_size).collect();
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new(HashMap::new());
+
+        thread::scope(|scope| {
+            for _ in 0..self.concurrency.min(chunks.len()) {
+                scope.spawn(|| {
+                    while let Some(chunk) = chunks.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        match query(chunk) {
+                            Ok(found) => results.lock().unwrap().extend(found),
+                            Err(e) => {
+                                log::warn!("{}", e);
+                                self.failed.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        results.into_inner().unwrap()
+    }
+}
+
+// Retry-After 只支持秒数形式，HTTP 日期形式按普通退避处理
+fn retry_after(resp: &Response) -> Option<Duration> {
+    let value = resp.headers().get(RETRY_AFTER)?.to_str().ok()?;
+    value.trim().parse::<u64>().ok().map(Duration::from_secs)
+}
+
+fn env_number<T: FromStr + std::fmt::Display>(key: &str, default: T) -> T {
+    match env::var(key) {
+        Ok(v) if !v.trim().is_empty() => v.trim().parse().unwrap_or_else(|_| {
+            log::warn!("{} 不是有效的数字: {}，使用默认值 {}", key, v, default);
+            default
+        }),
+        _ => default,
+    }
+}
+
+/// 原有的内部接口：POST {"iplist": [...]}，返回中文字段
+pub struct LegacyApiProvider {
+    client: Client,
+    api_url: String,
+    policy: RequestPolicy,
+}
+
+impl LegacyApiProvider {
+    pub fn new(api_url: String) -> crate::Result<Self> {
+        let client = Client::builder().timeout(Duration::from_secs(10)).build()?;
+        Ok(LegacyApiProvider { client, api_url, policy: RequestPolicy::from_env() })
+    }
+}
+
+impl GeoProvider for LegacyApiProvider {
+    fn name(&self) -> &str {
+        "api"
+    }
+
+    fn query(&self, ip_list: &[IpAddr]) -> HashMap<IpAddr, GeoLocation> {
+        self.policy.run_chunks(ip_list, LEGACY_BATCH_SIZE, |chunk| {
+            query_ip_batch(&self.client, &self.policy, chunk, &self.api_url)
+        })
+    }
+
+    fn source(&self) -> String {
+        format!("api {}", url_without_query(&self.api_url))
+    }
+
+    fn failed_count(&self) -> u64 {
+        self.policy.failed.load(Ordering::Relaxed)
+    }
+}
+
+/// 离线库（MMDB / IP2Location BIN）
+pub struct OfflineProvider {
+    db: OfflineDb,
+}
+
+impl OfflineProvider {
+    pub fn new(db: OfflineDb) -> Self {
+        OfflineProvider { db }
+    }
+}
+
+impl GeoProvider for OfflineProvider {
+    fn name(&self) -> &str {
+        "offline"
+    }
+
+    fn query(&self, ip_list: &[IpAddr]) -> HashMap<IpAddr, GeoLocation> {
+        ip_list
+            .iter()
+            .filter_map(|ip| self.db.lookup(*ip).map(|loc| (*ip, loc)))
+            .collect()
+    }
+
+    fn cacheable(&self) -> bool {
+        false
+    }
+}
+
+/// 响应 JSON 中各字段的路径，用点分隔，数组用下标，例如 data.0.country
+#[derive(Debug, Default)]
+struct FieldPaths {
+    country: Option<String>,
+    province: Option<String>,
+    city: Option<String>,
+    isp: Option<String>,
+    asn: Option<String>,
+    org: Option<String>,
+}
+
+/// 通用 JSON-over-HTTP 接口，每个 IP 请求一次。
+/// 默认字段映射适配 ip-api.com，其他接口通过 GEO_FIELD_* 配置
+pub struct HttpJsonProvider {
+    client: Client,
+    url_template: String,
+    method: Method,
+    body_template: Option<String>,
+    headers: Vec<(String, String)>,
+    fields: FieldPaths,
+    policy: RequestPolicy,
+}
+
+impl HttpJsonProvider {
+    /// 读取 GEO_HTTP_URL / GEO_HTTP_METHOD / GEO_HTTP_BODY / GEO_HTTP_HEADERS 与 GEO_FIELD_* 配置
+    pub fn from_env() -> crate::Result<Self> {
+        let url_template = env::var("GEO_HTTP_URL").unwrap_or_default();
+        if url_template.trim().is_empty() {
+            return Err(Error::Config("GEO_PROVIDER=http 时必须设置 GEO_HTTP_URL（用 {ip} 表示待查询的 IP）".to_string()));
+        }
+
+        let method = match env::var("GEO_HTTP_METHOD").unwrap_or_default().trim().to_uppercase().as_str() {
+            "" | "GET" => Method::GET,
+            "POST" => Method::POST,
+            other => return Err(Error::Config(format!("不支持的 GEO_HTTP_METHOD: {}（仅支持 GET/POST）", other))),
+        };
+
+        let body_template = env::var("GEO_HTTP_BODY").ok().filter(|b| !b.trim().is_empty());
+
+        // 多个请求头用分号分隔，例如 "Authorization: Bearer xxx; X-Key: yyy"
+        let headers = env::var("GEO_HTTP_HEADERS")
+            .unwrap_or_default()
+            .split(';')
+            .filter_map(|h| h.split_once(':'))
+            .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+            .filter(|(k, _)| !k.is_empty())
+            .collect();
+
+        // 未设置时使用 ip-api.com 的字段名，设置为空字符串表示不取该字段
+        let field = |key: &str, default: &str| -> Option<String> {
+            let path = env::var(key).unwrap_or_else(|_| default.to_string());
+            let path = path.trim();
+            (!path.is_empty()).then(|| path.to_string())
+        };
+        let fields = FieldPaths {
+            country: field("GEO_FIELD_COUNTRY", "country"),
+            province: field("GEO_FIELD_PROVINCE", "regionName"),
+            city: field("GEO_FIELD_CITY", "city"),
+            isp: field("GEO_FIELD_ISP", "isp"),
+            asn: field("GEO_FIELD_ASN", "as"),
+            org: field("GEO_FIELD_ORG", ""),
+        };
+
+        let client = Client::builder().timeout(Duration::from_secs(10)).build()?;
+
+        Ok(HttpJsonProvider {
+            client,
+            url_template,
+            method,
+            body_template,
+            headers,
+            fields,
+            policy: RequestPolicy::from_env(),
+        })
+    }
+
+    // 查询单个 IP；请求失败返回 Err，接口没有该 IP 的位置信息返回 Ok(None)
+    fn query_one(&self, ip: IpAddr) -> Result<Option<GeoLocation>, String> {
+        let ip_str = ip.to_string();
+        let url = self.url_template.replace("{ip}", &ip_str);
+
+        let build = || {
+            let mut request = self.client.request(self.method.clone(), &url);
+            for (key, value) in &self.headers {
+                request = request.header(key.as_str(), value.as_str());
+            }
+            if let Some(body) = &self.body_template {
+                request = request
+                    .header("Content-Type", "application/json")
+                    .body(body.replace("{ip}", &ip_str));
+            }
+            request
+        };
+
+        let resp = self
+            .policy
+            .send(build)
+            .map_err(|e| format!("{} ({})", e, ip_str))?;
+        let json: Value = resp
+            .json()
+            .map_err(|_e| format!("响应不是有效的 JSON,请查看 GEO_HTTP_URL: {} 是否正确", self.url_template))?;
+
+        let get = |path: &Option<String>| path.as_deref().map(|p| json_field(&json, p)).unwrap_or_default();
+        let mut loc = GeoLocation {
+            country: get(&self.fields.country),
+            province: get(&self.fields.province),
+            city: get(&self.fields.city),
+            isp: get(&self.fields.isp),
+            asn: None,
+            org: get(&self.fields.org),
+        };
+
+        // ASN 字段可能是数字，也可能是 "AS15169 Google LLC" 这样带组织名的字符串
+        let asn_text = get(&self.fields.asn);
+        if let Some((asn, org)) = parse_asn(&asn_text) {
+            loc.asn = Some(asn);
+            if loc.org.is_empty() {
+                loc.org = org;
+            }
+        }
+
+        // 私有地址等查询失败的响应通常没有任何位置字段
+        Ok((!loc.is_empty()).then_some(loc))
+    }
+}
+
+impl GeoProvider for HttpJsonProvider {
+    fn name(&self) -> &str {
+        "http"
+    }
+
+    fn query(&self, ip_list: &[IpAddr]) -> HashMap<IpAddr, GeoLocation> {
+        self.policy.run_chunks(ip_list, 1, |chunk| {
+            Ok(self.query_one(chunk[0])?.map(|loc| (chunk[0], loc)).into_iter().collect())
+        })
+    }
+
+    // 字段映射不同时同一接口的结果也不同
+    fn source(&self) -> String {
+        let f = &self.fields;
+        let fields: Vec<&str> = [&f.country, &f.province, &f.city, &f.isp, &f.asn, &f.org]
+            .map(|path| path.as_deref().unwrap_or(""))
+            .to_vec();
+        format!("http {} {} {}", self.method, url_without_query(&self.url_template), fields.join(","))
+    }
+
+    fn failed_count(&self) -> u64 {
+        self.policy.failed.load(Ordering::Relaxed)
+    }
+}
+
+// 去掉 URL 中的查询参数，避免把其中的 token 写入缓存文件
+fn url_without_query(url: &str) -> &str {
+    url.split('?').next().unwrap_or(url)
+}
+
+// 按点分路径取 JSON 字段，数字段作为数组下标；字符串和数字转为文本，其他类型视为空
+fn json_field(json: &Value, path: &str) -> String {
+    let mut current = json;
+    for key in path.split('.') {
+        let next = match current {
+            Value::Array(items) => key.parse::<usize>().ok().and_then(|i| items.get(i)),
+            _ => current.get(key),
+        };
+        match next {
+            Some(value) => current = value,
+            None => return String::new(),
+        }
+    }
+    match current {
+        Value::String(s) => s.trim().to_string(),
+        Value::Number(n) => n.to_string(),
+        _ => String::new(),
+    }
+}
+
+// 解析 "15169"、"AS15169" 或 "AS15169 Google LLC"，返回 (ASN, 组织名)
+fn parse_asn(text: &str) -> Option<(u32, String)> {
+    let text = text.trim();
+    let rest = text
+        .strip_prefix("AS")
+        .or_else(|| text.strip_prefix("as"))
+        .unwrap_or(text);
+    let digits_end = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+    let asn = rest[..digits_end].parse::<u32>().ok()?;
+    Some((asn, rest[digits_end..].trim().to_string()))
+}
@@ -0,0 +1,210 @@
+use crate::analyze::AggregateResult;
+use crate::csv_output::{sort_stats, SortKey};
+use crate::domains;
+use crate::flows::{format_timestamp, format_timestamp_secs};
+use crate::location::GeoLocation;
+use crate::stats::{self, TimeBucket};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::net::IpAddr;
+
+// 报告模板，样式与绘图脚本均内嵌在模板中，生成的文件可离线打开
+const TEMPLATE: &str = include_str!("../data/report_template.html");
+
+// 模板中替换为报告数据的占位符
+const DATA_PLACEHOLDER: &str = "/*__REPORT_DATA__*/null";
+
+// 没有时间序列时，活动时间段图中列出的 IP 数
+const TOP_SPANS: usize = 20;
+
+#[derive(Debug, Serialize)]
+struct ReportData<'a> {
+    generated_at: String,
+    local: String,
+    files: Vec<String>,
+    first_seen: String,
+    last_seen: String,
+    total_bytes: u64,
+    up_bytes: u64,
+    down_bytes: u64,
+    /// 是否查询了归属地
+    geo: bool,
+    /// 按报告的排序依据排列
+    ips: Vec<IpRow<'a>>,
+    countries: Vec<CountryRow>,
+    domains: Vec<DomainRow>,
+    /// 指定统计间隔时为各时间段的上下行流量，否则为空
+    timeline: Vec<TimelinePoint>,
+    /// 没有时间序列时，数据量最大的若干 IP 的首末出现时间
+    spans: Vec<Span>,
+}
+
+#[derive(Debug, Serialize)]
+struct IpRow<'a> {
+    ip: String,
+    total_pkts: u64,
+    total_bytes: u64,
+    up_bytes: u64,
+    down_bytes: u64,
+    domain: &'a str,
+    location: String,
+    asn: String,
+}
+
+#[derive(Debug, Serialize)]
+struct CountryRow {
+    name: String,
+    ips: usize,
+    bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct DomainRow {
+    domain: String,
+    registrable: String,
+    ips: usize,
+    total_pkts: u64,
+    total_bytes: u64,
+    up_bytes: u64,
+    down_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct TimelinePoint {
+    time: String,
+    up_bytes: u64,
+    down_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct Span {
+    ip: String,
+    /// 相对抓包开始时间的秒数
+    start: f64,
+    end: f64,
+    first_seen: String,
+    last_seen: String,
+}
+
+/// 写出单文件 HTML 报告：流量排行、上下行占比、国家分布、可搜索排序的域名表和时间线。
+/// IP 表格的初始顺序按 sort 排列，流量排行与活动时间段始终按总数据量
+pub fn write_html(
+    output_html: &str,
+    result: &AggregateResult,
+    // None 表示未查询归属地
+    locations: Option<&HashMap<IpAddr, GeoLocation>>,
+    sort: SortKey,
+) -> crate::Result<()> {
+    let data = report_data(result, locations, sort);
+    // 防止数据中的 "</script>" 提前结束脚本
+    let json = serde_json::to_string(&data)?.replace("</", "<\\/");
+    fs::write(output_html, TEMPLATE.replacen(DATA_PLACEHOLDER, &json, 1))?;
+    Ok(())
+}
+
+fn report_data<'a>(
+    result: &'a AggregateResult,
+    locations: Option<&HashMap<IpAddr, GeoLocation>>,
+    sort: SortKey,
+) -> ReportData<'a> {
+    let mut sorted: Vec<(&IpAddr, &'a stats::FlowStat)> = result.stats.iter().collect();
+    sorted.sort_by(|a, b| b.1.total_bytes.cmp(&a.1.total_bytes).then_with(|| a.0.cmp(b.0)));
+    let location = |ip: &IpAddr| locations.and_then(|locs| locs.get(ip));
+
+    let ips = sort_stats(&result.stats, sort, locations)
+        .into_iter()
+        .map(|(ip, stat)| IpRow {
+            ip: ip.to_string(),
+            total_pkts: stat.total_pkts,
+            total_bytes: stat.total_bytes,
+            up_bytes: stat.up_bytes,
+            down_bytes: stat.down_bytes,
+            domain: domains::primary_domain(stat).unwrap_or_default(),
+            location: location(&ip).map(|loc| loc.combined()).unwrap_or_default(),
+            asn: location(&ip).map(|loc| loc.asn_label()).unwrap_or_default(),
+        })
+        .collect();
+
+    // 国家分布，查不到归属地的 IP 归入“未知”
+    let mut countries: HashMap<String, CountryRow> = HashMap::new();
+    if locations.is_some() {
+        for (ip, stat) in &sorted {
+            let name = match location(ip).map(|loc| loc.country.as_str()) {
+                Some(country) if !country.is_empty() => country.to_string(),
+                _ => "未知".to_string(),
+            };
+            let row = countries.entry(name.clone()).or_insert(CountryRow { name, ips: 0, bytes: 0 });
+            row.ips += 1;
+            row.bytes += stat.total_bytes;
+        }
+    }
+    let mut countries: Vec<CountryRow> = countries.into_values().collect();
+    countries.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.name.cmp(&b.name)));
+
+    let domains = domains::aggregate_by_domain(&result.stats, false)
+        .into_iter()
+        .map(|(domain, stat)| DomainRow {
+            registrable: if domain == domains::UNATTRIBUTED { domain.clone() } else { domains::registrable_domain(&domain) },
+            domain,
+            ips: stat.ips.len(),
+            total_pkts: stat.total_pkts,
+            total_bytes: stat.total_bytes,
+            up_bytes: stat.up_bytes,
+            down_bytes: stat.down_bytes,
+        })
+        .collect();
+
+    // 各 IP 的时间段合计为整体时间线
+    let mut buckets: BTreeMap<i64, TimeBucket> = BTreeMap::new();
+    for stat in result.stats.values() {
+        for (start, bucket) in &stat.buckets {
+            let entry = buckets.entry(*start).or_default();
+            entry.up_bytes += bucket.up_bytes;
+            entry.down_bytes += bucket.down_bytes;
+        }
+    }
+    let timeline = buckets
+        .into_iter()
+        .map(|(start, bucket)| TimelinePoint {
+            time: format_timestamp_secs(start),
+            up_bytes: bucket.up_bytes,
+            down_bytes: bucket.down_bytes,
+        })
+        .collect::<Vec<_>>();
+
+    let period = stats::capture_period(&result.stats);
+    let spans = match period {
+        Some((first, _)) if timeline.is_empty() => sorted
+            .iter()
+            .filter(|(_, stat)| stat.first_seen > 0.0)
+            .take(TOP_SPANS)
+            .map(|(ip, stat)| Span {
+                ip: ip.to_string(),
+                start: stat.first_seen - first,
+                end: stat.last_seen - first,
+                first_seen: format_timestamp(stat.first_seen),
+                last_seen: format_timestamp(stat.last_seen),
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    let (first_seen, last_seen) = period.unwrap_or_default();
+
+    ReportData {
+        generated_at: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        local: result.local.to_string(),
+        files: result.files.iter().map(|f| f.path.to_string_lossy().to_string()).collect(),
+        first_seen: format_timestamp(first_seen),
+        last_seen: format_timestamp(last_seen),
+        total_bytes: result.total,
+        up_bytes: result.up,
+        down_bytes: result.down,
+        geo: locations.is_some(),
+        ips,
+        countries,
+        domains,
+        timeline,
+        spans,
+    }
+}
@@ -0,0 +1,173 @@
+use crate::analyze::AggregateResult;
+use crate::csv_output::{sort_stats, SortKey};
+use crate::domains;
+use crate::location::GeoLocation;
+use crate::stats::{self, protocol_name, FlowStat};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::IpAddr;
+
+/// JSON 报告：元数据、总计与按排序依据排列的各远端 IP
+#[derive(Debug, Serialize)]
+struct JsonReport<'a> {
+    metadata: Metadata,
+    totals: Totals,
+    entries: Vec<Entry<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct Metadata {
+    generator: &'static str,
+    version: &'static str,
+    /// 报告生成时间（RFC 3339）
+    generated_at: String,
+    files: Vec<String>,
+    local: String,
+    /// 是否查询了归属地；为 false 时各条目的 location 均为 null
+    geo: bool,
+    /// 抓包中首个、最后一个数据包的时间（Unix 秒）
+    first_seen: Option<f64>,
+    last_seen: Option<f64>,
+}
+
+/// 总计：entries 为报告中的条目数（已应用最小数据量与过滤规则），
+/// 字节数为全部流量的总计，包含过滤掉的 IP
+#[derive(Debug, Serialize)]
+struct Totals {
+    entries: usize,
+    bytes: u64,
+    up_bytes: u64,
+    down_bytes: u64,
+}
+
+/// 单个远端 IP 的统计，JSONL 中每行一个
+#[derive(Debug, Serialize)]
+struct Entry<'a> {
+    ip: IpAddr,
+    total_pkts: u64,
+    total_bytes: u64,
+    up_pkts: u64,
+    up_bytes: u64,
+    down_pkts: u64,
+    down_bytes: u64,
+    first_seen: Option<f64>,
+    last_seen: Option<f64>,
+    /// 归属域名，规则同按域名汇总的报表
+    domain: Option<&'a str>,
+    /// 数据包中出现的全部域名（HTTP Host、TLS SNI、DNS 查询名）
+    domains: Vec<&'a str>,
+    /// HTTP Host / TLS SNI 及其出现次数
+    hosts: BTreeMap<&'a str, u64>,
+    resolved_names: Vec<&'a str>,
+    /// 每条 CNAME 链从查询的域名到 A/AAAA 记录的所有者
+    cname_chains: Vec<Vec<&'a str>>,
+    ports: Vec<PortEntry>,
+    location: Option<&'a GeoLocation>,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    matched_rule: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct PortEntry {
+    protocol: String,
+    /// 无端口的协议为 null
+    port: Option<u16>,
+    pkts: u64,
+    bytes: u64,
+}
+
+impl<'a> Entry<'a> {
+    fn new(ip: IpAddr, stat: &'a FlowStat, locations: Option<&'a HashMap<IpAddr, GeoLocation>>) -> Self {
+        let mut domains: Vec<&str> = stat.domains.iter().map(|d| d.as_str()).collect();
+        domains.sort();
+        Entry {
+            ip,
+            total_pkts: stat.total_pkts,
+            total_bytes: stat.total_bytes,
+            up_pkts: stat.up_pkts,
+            up_bytes: stat.up_bytes,
+            down_pkts: stat.down_pkts,
+            down_bytes: stat.down_bytes,
+            first_seen: timestamp(stat.first_seen),
+            last_seen: timestamp(stat.last_seen),
+            domain: domains::primary_domain(stat),
+            domains,
+            hosts: stat.hosts.iter().map(|(host, count)| (host.as_str(), *count)).collect(),
+            resolved_names: stat.resolved_names.iter().map(|d| d.as_str()).collect(),
+            cname_chains: stat.cname_chains.iter().map(|chain| chain.split(" -> ").collect()).collect(),
+            ports: stat
+                .sorted_ports()
+                .into_iter()
+                .map(|((proto, port), port_stat)| PortEntry {
+                    protocol: protocol_name(proto),
+                    port: (port != 0).then_some(port),
+                    pkts: port_stat.pkts,
+                    bytes: port_stat.bytes,
+                })
+                .collect(),
+            location: locations.and_then(|locs| locs.get(&ip)),
+            matched_rule: &stat.matched_rule,
+        }
+    }
+}
+
+/// 写出单个 JSON 文档，字节数均为原始整数
+pub fn write_json(
+    output_json: &str,
+    result: &AggregateResult,
+    // None 表示未查询归属地
+    locations: Option<&HashMap<IpAddr, GeoLocation>>,
+    sort: SortKey,
+) -> crate::Result<()> {
+    let sorted_stats = sort_stats(&result.stats, sort, locations);
+    let period = stats::capture_period(&result.stats);
+
+    let report = JsonReport {
+        metadata: Metadata {
+            generator: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            generated_at: chrono::Local::now().to_rfc3339(),
+            files: result.files.iter().map(|f| f.path.to_string_lossy().to_string()).collect(),
+            local: result.local.to_string(),
+            geo: locations.is_some(),
+            first_seen: period.map(|(first, _)| first),
+            last_seen: period.map(|(_, last)| last),
+        },
+        totals: Totals {
+            entries: sorted_stats.len(),
+            bytes: result.total,
+            up_bytes: result.up,
+            down_bytes: result.down,
+        },
+        entries: sorted_stats.iter().map(|(ip, stat)| Entry::new(*ip, stat, locations)).collect(),
+    };
+
+    let mut writer = BufWriter::new(File::create(output_json)?);
+    serde_json::to_writer_pretty(&mut writer, &report)?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// 写出 JSON Lines，每行一个远端 IP，字段同 JSON 报告中的 entries
+pub fn write_jsonl(
+    output_jsonl: &str,
+    stats_map: &HashMap<IpAddr, FlowStat>,
+    locations: Option<&HashMap<IpAddr, GeoLocation>>,
+    sort: SortKey,
+) -> crate::Result<()> {
+    let mut writer = BufWriter::new(File::create(output_jsonl)?);
+    for (ip, stat) in sort_stats(stats_map, sort, locations) {
+        serde_json::to_writer(&mut writer, &Entry::new(ip, stat, locations))?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// 没有时间戳时为 null
+fn timestamp(ts: f64) -> Option<f64> {
+    (ts > 0.0).then_some(ts)
+}
@@ -0,0 +1,53 @@
+//! PcapRacer 流量分析库：统计抓包文件中本机与各远端 IP 的流量，并附上归属地、域名等信息。
+//!
+//! 分析流程分为四步，可单独调用，也可用 [`analyze::analyze_single_file`] 等函数一步完成：
+//!
+//! 1. 读取：[`packet::for_each_packet`] 逐个解析数据包（内置解析器或 tshark）；
+//! 2. 聚合：[`analyze::parse_and_aggregate`] 按远端 IP 汇总，得到 [`AggregateResult`]，
+//!    （合并多个文件后）调用 [`AggregateResult::attribute_names`] 标注 DNS 解析出的域名；
+//! 3. 补充：[`analyze::enrich`] 应用过滤规则并查询归属地；
+//! 4. 输出：[`analyze::write_report`] 写出报告，返回 [`Report`]。
+//!
+//! 所有错误均为 [`Error`]，库内不会终止进程。
+//! 可恢复的问题（如抓包文件末尾截断、配置值无效而使用默认值）通过 `log` 记录为警告，
+//! 库本身不会写标准输出或标准错误。
+//!
+//! ```no_run
+//! use pcap_racer::{analyze, AnalyzeOptions, GeoSource};
+//!
+//! fn run(options: &AnalyzeOptions) -> pcap_racer::Result<()> {
+//!     let geo = GeoSource::from_env()?;
+//!     for report in analyze::analyze_single_file("capture.pcapng", &geo, options)? {
+//!         println!("{}: {} 个远端 IP", report.output.display(), report.rows);
+//!     }
+//!     Ok(())
+//! }
+//! ```
+
+pub mod analyze;
+pub mod csv_output;
+pub mod dns;
+pub mod domains;
+pub mod error;
+pub mod filter;
+pub mod flows;
+pub mod geo_cache;
+pub mod geo_db;
+pub mod geo_provider;
+pub mod html_output;
+pub mod json_output;
+pub mod location;
+pub mod packet;
+pub mod stats;
+pub mod xlsx_output;
+
+mod pcap;
+mod tshark;
+
+pub use analyze::{AggregateResult, AnalyzeOptions, OutputFormat, Report};
+pub use csv_output::CsvOptions;
+pub use error::{Error, Result};
+pub use filter::Filter;
+pub use location::{GeoLocation, GeoSource};
+pub use packet::{Backend, PacketRecord};
+pub use stats::{FlowStat, LocalHost, NetConfig};
@@ -1,9 +1,13 @@
-use serde::Deserialize;
+use crate::error::Error;
+use crate::geo_cache::GeoCache;
+use crate::geo_db::OfflineDb;
+use crate::geo_provider::{GeoProvider, HttpJsonProvider, LegacyApiProvider, OfflineProvider, RequestPolicy};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::env;
 use reqwest::blocking::Client;
-use std::time::Duration;
 use serde_json::json;
-use std::net::{IpAddr, Ipv4Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 #[derive(Debug, Deserialize)]
 pub struct RawIpInfo {
@@ -21,120 +25,191 @@ pub struct RawIpInfo {
 
     #[serde(rename = "运营商", default)]
     pub isp: String,
-    // 还有其他字段你可以加上
 }
 
-pub fn query_ip_locations(
-    ip_list: &[String],
-    batch_size: usize,
-    api_url: &str,
-) -> HashMap<String, String> {
-    let client = Client::builder()
-        .timeout(Duration::from_secs(10))
-        .build()
-        .unwrap();
+/// 结构化的归属地信息，各来源统一转换为此结构
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GeoLocation {
+    pub country: String,
+    pub province: String,
+    pub city: String,
+    pub isp: String,
+    #[serde(default)]
+    pub asn: Option<u32>,
+    #[serde(default)]
+    pub org: String,
+}
 
-    let mut results = HashMap::new();
+impl GeoLocation {
+    pub fn is_empty(&self) -> bool {
+        self.country.is_empty()
+            && self.province.is_empty()
+            && self.city.is_empty()
+            && self.isp.is_empty()
+            && self.asn.is_none()
+    }
+
+    /// 拼接为旧版的单列归属地字符串（国家省份城市运营商），不含 ASN
+    pub fn combined(&self) -> String {
+        format!("{}{}{}{}", self.country, self.province, self.city, self.isp).trim().to_string()
+    }
 
-    for chunk in ip_list.chunks(batch_size) {
-        let payload = json!({ "iplist": chunk });
-        let full_url = format!("{}", api_url);
+    /// ASN 及其组织，如 "AS15169 Google LLC"；没有 ASN 时为空
+    pub fn asn_label(&self) -> String {
+        match self.asn {
+            Some(asn) => format!("AS{} {}", asn, self.org).trim().to_string(),
+            None => String::new(),
+        }
+    }
+}
 
-        match client.post(&full_url).json(&payload).send() {
-            Ok(resp) => {
-                if resp.status() != reqwest::StatusCode::OK {
-                    eprintln!("❌ HTTP错误，状态码: {}", resp.status());
-                    continue;
+/// 归属地查询统计
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GeoStats {
+    /// (缓存命中数, 未命中数)，未启用缓存时为 None
+    pub cache: Option<(u64, u64)>,
+    /// 重试后仍查询失败的 IP 数
+    pub failed: u64,
+}
+
+/// 归属地查询来源：一个查询提供方，在线提供方可附带磁盘缓存
+pub struct GeoSource {
+    provider: Option<Box<dyn GeoProvider>>,
+    cache: Option<GeoCache>,
+}
+
+impl GeoSource {
+    /// 不查询归属地
+    pub fn none() -> Self {
+        GeoSource { provider: None, cache: None }
+    }
+
+    /// 通过 GEO_PROVIDER 选择提供方（api / http / offline / none）。
+    /// 未设置时保持原有行为：配置了 GEO_DB_PATH 用离线库，否则使用 API_URL
+    pub fn from_env() -> crate::Result<Self> {
+        let db_path = env::var("GEO_DB_PATH").unwrap_or_default();
+        let api_url = env::var("API_URL").unwrap_or_default();
+
+        let kind = match env::var("GEO_PROVIDER") {
+            Ok(v) if !v.trim().is_empty() => v.trim().to_lowercase(),
+            _ if !db_path.trim().is_empty() => "offline".to_string(),
+            _ if !api_url.is_empty() => "api".to_string(),
+            _ => "none".to_string(),
+        };
+
+        let provider: Box<dyn GeoProvider> = match kind.as_str() {
+            "none" => return Ok(GeoSource::none()),
+            "api" => {
+                if api_url.is_empty() {
+                    return Err(Error::Config("GEO_PROVIDER=api 时必须设置 API_URL".to_string()));
                 }
-        
-                match resp.text() {
-                    Ok(text) => {
-                        // println!("接口返回原始 JSON:\n{}", text);
-        
-                        match serde_json::from_str::<Vec<RawIpInfo>>(&text) {
-                            Ok(ip_infos) => {
-                                for loc in ip_infos {
-                                    let location_str = format!("{}{}{}{}", loc.country, loc.province, loc.city, loc.isp);
-                                    results.insert(loc.ip, location_str);
-                                }
-                            }
-                            Err(_e) => {
-                                eprintln!("❌ 反序列化 IP 列表失败,请查看api: {} 是否正确",api_url);
-                            }
-                        }
-                    }
-                    Err(_e) => {
-                        eprintln!("❌ 读取响应体文本失败,请查看api: {} 是否正确",api_url);
-                    }
+                Box::new(LegacyApiProvider::new(api_url)?)
+            }
+            "http" => Box::new(HttpJsonProvider::from_env()?),
+            "offline" => {
+                // 多个库文件用逗号分隔
+                let paths: Vec<&str> = db_path
+                    .split(',')
+                    .map(|p| p.trim())
+                    .filter(|p| !p.is_empty())
+                    .collect();
+                if paths.is_empty() {
+                    return Err(Error::Config("GEO_PROVIDER=offline 时必须设置 GEO_DB_PATH".to_string()));
                 }
+                Box::new(OfflineProvider::new(OfflineDb::open(&paths)?))
             }
-            Err(_e) => {
-                eprintln!("❌ 请求失败,请查看api: {} 是否正确",api_url);
+            other => {
+                return Err(Error::Config(format!("不支持的 GEO_PROVIDER: {}（可选 api / http / offline / none）", other)));
             }
-        }       
-        
-        // 请求完毕后，sleep 2秒，防止被限速
-        std::thread::sleep(std::time::Duration::from_secs(1));
+        };
+
+        let cache = if provider.cacheable() { GeoCache::from_env(&provider.source()) } else { None };
+        Ok(GeoSource { provider: Some(provider), cache })
     }
 
-    results
-}
+    /// 缓存命中情况与重试后仍查询失败的 IP 数
+    pub fn stats(&self) -> GeoStats {
+        GeoStats {
+            cache: self.cache.as_ref().map(|cache| cache.counts()),
+            failed: self.provider.as_ref().map_or(0, |p| p.failed_count()),
+        }
+    }
 
+    pub fn is_available(&self) -> bool {
+        self.provider.is_some()
+    }
 
-/// 查询单个 IP 的位置信息，先验证 IPv4 格式和公网性
-pub fn query_single_ip(ip: &str, api_url: &str) -> Option<RawIpInfo> {
-    // 检查是否是合法的 IPv4
-    let parsed_ip: Ipv4Addr = match ip.parse() {
-        Ok(IpAddr::V4(addr)) => addr,
-        _ => {
-            eprintln!("❌ 无效的 IPv4 地址: {}", ip);
-            return None;
-        }
-    };
+    pub fn provider_name(&self) -> Option<&str> {
+        self.provider.as_ref().map(|p| p.name())
+    }
 
-    // 检查是否是公网 IP
-    if !is_public_ipv4(&parsed_ip) {
-        eprintln!("❌ 非公网 IPv4 地址: {}", ip);
-        return None;
+    pub fn query_locations(&self, ip_list: &[IpAddr]) -> HashMap<IpAddr, GeoLocation> {
+        let Some(provider) = &self.provider else {
+            return HashMap::new();
+        };
+        match &self.cache {
+            Some(cache) => {
+                // 先查缓存，只请求缓存中没有或已过期的 IP
+                let (mut results, missing) = cache.lookup(ip_list);
+                let fetched = provider.query(&missing);
+                cache.store(&fetched);
+                results.extend(fetched);
+                results
+            }
+            None => provider.query(ip_list),
+        }
     }
+}
 
-    // 继续进行查询
-    let client = Client::builder()
-        .timeout(Duration::from_secs(10))
-        .build()
-        .unwrap();
+/// 内部接口的一次批量查询：POST {"iplist": [...]}，失败时返回错误信息，由调用方决定重试
+pub fn query_ip_batch(
+    client: &Client,
+    policy: &RequestPolicy,
+    chunk: &[IpAddr],
+    api_url: &str,
+) -> Result<HashMap<IpAddr, GeoLocation>, String> {
+    let iplist: Vec<String> = chunk.iter().map(|ip| ip.to_string()).collect();
+    let payload = json!({ "iplist": iplist });
 
-    let payload = json!({ "iplist": [ip] });
-    let full_url = format!("{}", api_url);
+    let resp = policy.send(|| client.post(api_url).json(&payload))?;
+    let text = resp
+        .text()
+        .map_err(|_e| format!("读取响应体文本失败,请查看api: {} 是否正确", api_url))?;
 
-    match client.post(&full_url).json(&payload).send() {
-        Ok(resp) => {
-            if resp.status() != reqwest::StatusCode::OK {
-                eprintln!("❌ HTTP错误，状态码: {}", resp.status());
-                return None;
-            }
+    let ip_infos = serde_json::from_str::<Vec<RawIpInfo>>(&text)
+        .map_err(|_e| format!("反序列化 IP 列表失败,请查看api: {} 是否正确", api_url))?;
 
-            match resp.text() {
-                Ok(text) => {
-                    match serde_json::from_str::<Vec<RawIpInfo>>(&text) {
-                        Ok(mut ip_infos) => ip_infos.pop(),
-                        Err(e) => {
-                            eprintln!("❌ 反序列化单个 IP 失败: {}", e);
-                            None
-                        }
-                    }
-                }
-                Err(e) => {
-                    eprintln!("❌ 读取响应体文本失败: {}", e);
-                    None
-                }
-            }
-        }
-        Err(e) => {
-            eprintln!("❌ 请求失败: {}", e);
-            None
-        }
+    let mut results = HashMap::new();
+    for loc in ip_infos {
+        // 接口返回的 IPv6 写法可能与请求不同，统一解析后作为键
+        let Ok(ip) = loc.ip.parse::<IpAddr>() else {
+            continue;
+        };
+        results.insert(ip, GeoLocation {
+            country: loc.country,
+            province: loc.province,
+            city: loc.city,
+            isp: loc.isp,
+            ..Default::default()
+        });
+    }
+    Ok(results)
+}
+
+/// 验证单个 IP 的格式和公网性，通过时返回解析后的地址
+pub fn parse_public_ip(ip: &str) -> crate::Result<IpAddr> {
+    // 检查是否是合法的 IPv4 / IPv6
+    let parsed_ip: IpAddr = ip.parse().map_err(|_| Error::InvalidIp(ip.to_string()))?;
+
+    // 检查是否是公网 IP
+    let is_public = match &parsed_ip {
+        IpAddr::V4(addr) => is_public_ipv4(addr),
+        IpAddr::V6(addr) => is_public_ipv6(addr),
+    };
+    if !is_public {
+        return Err(Error::NotPublicIp(parsed_ip));
     }
+    Ok(parsed_ip)
 }
 
 fn is_public_ipv4(ip: &Ipv4Addr) -> bool {
@@ -144,4 +219,10 @@ fn is_public_ipv4(ip: &Ipv4Addr) -> bool {
         || ip.is_broadcast()
         || ip.octets()[0] == 0   // 0.0.0.0/8
         || ip.octets()[0] >= 224) // 224.0.0.0/4 为多播和保留地址
-}
\ No newline at end of file
+}
+
+fn is_public_ipv6(ip: &Ipv6Addr) -> bool {
+    // 全球单播地址 2000::/3，排除文档地址 2001:db8::/32
+    (ip.segments()[0] & 0xE000) == 0x2000
+        && !(ip.segments()[0] == 0x2001 && ip.segments()[1] == 0x0DB8)
+}
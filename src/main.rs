@@ -1,210 +1,419 @@
-mod tshark;
-mod stats;
-mod location;
-mod csv_output;
-mod analyze;
-
 use std::env;
 use std::time::Instant;
 use std::process::Command;
-use std::fs;
+use std::path::{Path, PathBuf};
+use clap::{ArgAction, Args, CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
 use dotenvy::from_path;
+use indicatif::{ProgressBar, ProgressStyle};
+use pcap_racer::analyze::{self, AnalyzeOptions, OutputFormat, Report};
+use pcap_racer::stats::{LocalHost, NetConfig};
+use pcap_racer::csv_output::{ByteFormat, ByteUnit, Column, SortKey};
+use pcap_racer::{Backend, CsvOptions, Error, Filter, GeoSource, Result};
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // 加载 .env 文件
-    load_env_from_exe_dir();
+/// 🚀 PcapRacer 流量分析统计工具
+///
+/// 输出的 CSV 文件名默认为 源文件/源文件名.csv
+#[derive(Parser)]
+#[command(name = "PcapRacer", version, about, disable_version_flag = true, arg_required_else_help = true)]
+struct Cli {
+    /// 显示版本
+    #[arg(short = 'v', long = "version", action = ArgAction::Version)]
+    version: Option<bool>,
 
-    let api_url = env::var("API_URL").unwrap_or_else(|_| {
-        String::from("")
-    });
+    #[command(subcommand)]
+    command: Commands,
+}
 
-    if api_url.is_empty() {
-        eprintln!("⚠️ API_URL 未设置或为空，位置信息将无法查询。请在 .env 文件中设置正确的 API_URL。");
-    }
+#[derive(Subcommand)]
+enum Commands {
+    /// 查询单个 IP 的归属地（支持 IPv4 / IPv6）
+    Lookup {
+        /// 要查询的 IP
+        ip: String,
+    },
+    /// 分析单个 pcap/pcapng 文件，或逐个分析文件夹内的所有文件
+    Analyze {
+        /// 要分析的 pcap 文件或文件夹
+        input: PathBuf,
+        #[command(flatten)]
+        args: AnalyzeArgs,
+    },
+    /// 分析文件夹内的所有 pcap 文件，并将结果汇总成一个文件
+    Merge {
+        /// 要分析的 pcap 文件夹
+        dir: PathBuf,
+        #[command(flatten)]
+        args: AnalyzeArgs,
+    },
+    /// 生成 shell 补全脚本，如 PcapRacer completions bash > pcapracer.bash
+    Completions {
+        shell: Shell,
+    },
+}
+
+#[derive(Args)]
+struct AnalyzeArgs {
+    /// 输出文件路径，逐个分析文件夹时为输出目录（默认与输入同名）
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// 输出格式
+    #[arg(long, value_enum, default_value_t = OutputFormat::Csv)]
+    format: OutputFormat,
+
+    /// 本机地址：IP、CIDR 网段或 MAC 地址，多个用逗号分隔（默认自动定位）
+    #[arg(long, visible_alias = "local", value_name = "IP|CIDR|MAC", value_parser = LocalHost::parse)]
+    local_ip: Option<LocalHost>,
+
+    /// CSV 主表输出的列及顺序，逗号分隔（默认输出全部常用列）
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "COLUMNS")]
+    columns: Vec<Column>,
+
+    /// 各 IP 的排序依据（CSV、JSON、Excel、HTML 表格）
+    #[arg(long, value_enum, value_name = "KEY", default_value_t = SortKey::Packets)]
+    sort: SortKey,
+
+    /// 业务说明合并为一列，域名之间用分隔符连接（默认 "; "），如 --join-domains=" | "
+    #[arg(long, value_name = "SEP", num_args = 0..=1, require_equals = true, default_missing_value = "; ")]
+    join_domains: Option<String>,
+
+    /// CSV 中数据量列的单位：human（默认）为自动选择单位的可读格式，bytes 为字节数，kb/mb/gb 为固定单位的数值
+    #[arg(long, value_enum, value_name = "UNIT")]
+    bytes_unit: Option<ByteUnit>,
+
+    /// 数据量单位的换算进制
+    #[arg(long, value_name = "BASE", default_value_t = 1024, value_parser = parse_unit_base)]
+    unit_base: u64,
+
+    /// 在每个数据量列之后追加一列可读格式，需同时以 --bytes-unit 指定数值单位
+    #[arg(long, requires = "bytes_unit")]
+    human_bytes: bool,
+
+    /// 总数据量低于该字节数的 IP 不输出
+    #[arg(long, value_name = "BYTES", default_value_t = 1024)]
+    min_bytes: u64,
+
+    /// 仅保留匹配规则的 IP：关键字、re:正则、通配符、IP/CIDR、AS编号或 @列表文件，逗号分隔（re: 之后整体为一条正则），可重复
+    #[arg(short = 'c', long, value_name = "RULES")]
+    filter: Vec<String>,
+
+    /// 排除匹配规则的 IP，格式同 --filter，可重复
+    #[arg(long, value_name = "RULES")]
+    exclude: Vec<String>,
+
+    /// 并发分析文件的线程数（默认等于 CPU 核数）
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    threads: Option<u16>,
+
+    /// 使用 tshark 解析（默认内置解析器）
+    #[arg(long)]
+    tshark: bool,
+
+    /// 跳过归属地查询
+    #[arg(long)]
+    no_geo: bool,
+
+    /// 归属地合并为单列（默认分列）
+    #[arg(long)]
+    legacy_location: bool,
+
+    /// 额外输出协议端口明细 _ports.csv
+    #[arg(long)]
+    ports: bool,
+
+    /// 额外输出五元组会话表 _flows.csv
+    #[arg(long)]
+    flows: bool,
+
+    /// 额外输出按域名汇总的 _domains.csv 与 _registrable.csv
+    #[arg(long)]
+    domains: bool,
 
-    let tshark_tsv = "temp_output.tsv";
+    /// 按时间段输出时间序列 _timeseries.csv，如 60、30s、5m、1h
+    #[arg(long, value_name = "INTERVAL", value_parser = parse_interval_arg)]
+    interval: Option<u64>,
 
-    let mut args: Vec<String> = env::args().collect();
-    // 解析 -c 参数（可选）
-    let mut company: Option<String> = None;
-    if let Some(pos) = args.iter().position(|a| a == "-c") {
-        if pos + 1 < args.len() {
-            company = Some(args[pos + 1].clone());
-            // 移除 -c 和关键字，防止干扰 argc 逻辑
-            args.drain(pos..=pos+1);
-        } else {
-            eprintln!("❌ -c 参数后需要一个字符串");
-            std::process::exit(1);
+    /// 每个内网主机单独输出一份报告
+    #[arg(long)]
+    multi_host: bool,
+}
+
+// 将库输出的警告打印到标准错误
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Warn
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            let prefix = if record.level() == log::Level::Error { "❌" } else { "⚠️" };
+            eprintln!("{} {}", prefix, record.args());
         }
     }
 
-    let argc = args.len();
+    fn flush(&self) {}
+}
 
-    match argc {
-        0 => {
-            eprintln!("❌ 参数错误！");
-            print_usage();
-            std::process::exit(1);
-        }
-        1 => {
-            print_usage();
-            std::process::exit(0);
-        }
-        2 => match args[1].as_str() {
-            "-v" | "--version" => {
-                println!("Version: {}", env!("CARGO_PKG_VERSION"));
-                std::process::exit(0);
-            }
-            "-h" | "--help" => {
-                print_usage();
-                std::process::exit(0);
-            }
-            path => {
-                // 自动判断路径类型
-                use std::path::Path;
-    
-                let input_path = Path::new(path);
-                if !input_path.exists() {
-                    eprintln!("❌ 输入路径不存在: {}", path);
-                    std::process::exit(1);
-                }
-    
-                if !check_tshark() {
-                    std::process::exit(1);
-                }
-    
-                let start_time = Instant::now();
-    
-                if input_path.is_file() {
-                    // 文件：调用 analyze_single_file
-                    analyze::analyze_single_file(path, &api_url, tshark_tsv,company.as_deref())?;
-                } else if input_path.is_dir() {
-                    // 目录：调用 analyze_directory
-                    analyze::analyze_directory(path, &api_url, tshark_tsv,company.as_deref())?;
-                } else {
-                    eprintln!("❌ 无法识别输入路径类型: {}", path);
-                    std::process::exit(1);
-                }
-    
-                let duration = start_time.elapsed();
-                println!("程序总耗时: {:.2?}", duration);
-            }
-        },
-        3 => match args[1].as_str() {
-            "-i" => {
-                if api_url.is_empty() {
-                    eprintln!("❌ 此项必须设置API_URL环境变量，位置信息查询API不能为空！");
-                    std::process::exit(0);
-                }
-                analyze::run_analysis_one_ip(&args[2], &api_url);
-            }
-            "-f" => {
-                if !check_tshark() {
-                    std::process::exit(1);
-                }
-                let start_time = Instant::now();
+static LOGGER: StderrLogger = StderrLogger;
 
-                analyze::analyze_single_file(&args[2], &api_url,tshark_tsv,company.as_deref())?;
+fn main() {
+    // 加载 .env 文件
+    load_env_from_exe_dir();
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(log::LevelFilter::Warn);
+    }
 
-                let duration = start_time.elapsed();
-                println!("程序总耗时: {:.2?}", duration);
-            }
-            "-F" => {
-                if !check_tshark() {
-                    std::process::exit(1);
-                }
-                let start_time = Instant::now();
+    let cli = Cli::parse_from(translate_legacy_args(env::args().collect()));
+    if let Err(e) = run(cli) {
+        eprintln!("{}", error_message(&e));
+        std::process::exit(1);
+    }
+}
 
-                analyze::analyze_directory(&args[2], &api_url, tshark_tsv,company.as_deref())?;
+// 错误信息加上提示符号，可通过命令行参数解决的错误附上参数说明
+fn error_message(e: &Error) -> String {
+    let mut root = e;
+    while let Error::File { source, .. } = root {
+        root = source;
+    }
+    let (prefix, hint) = match root {
+        Error::NoCaptureFiles(_) => ("⚠️", ""),
+        Error::LocalHostNotFound(_) => ("❌", "，可通过 --local-ip 指定本机地址，或使用 --multi-host 按内网主机分别统计"),
+        Error::NoInternalHosts => ("❌", "，可通过 INTERNAL_NETS 或 --local-ip 指定内网主机范围"),
+        _ => ("❌", ""),
+    };
+    format!("{} {}{}", prefix, e, hint)
+}
 
-                let duration = start_time.elapsed();
-                println!("程序总耗时: {:.2?}", duration);
+fn run(cli: Cli) -> Result<()> {
+    match cli.command {
+        Commands::Lookup { ip } => {
+            let geo = GeoSource::from_env()?;
+            if !geo.is_available() {
+                return Err(Error::Config("此项必须设置GEO_PROVIDER、API_URL或GEO_DB_PATH环境变量，位置信息查询来源不能为空！".to_string()));
             }
-            _ => {
-                eprintln!("❌ 参数错误！");
-                print_usage();
-                std::process::exit(1);
-            }
-        },
-        4 => {
-            if args[1] == "-F" && args[2] == "-A" {
-                if !check_tshark() {
-                    std::process::exit(1);
+            match analyze::lookup_ip(&ip, &geo)? {
+                Some(location) => {
+                    println!("IP: {}", ip);
+                    println!("位置信息: {}{}{}{}", location.country, location.province, location.city, location.isp);
+                    if let Some(asn) = location.asn {
+                        println!("ASN: AS{} {}", asn, location.org);
+                    }
                 }
-                let start_time = Instant::now();
-
-                analyze::analyze_directory_merged(&args[3], &api_url, tshark_tsv,company.as_deref())?;
+                None => println!("未能查询到该 IP 的归属信息"),
+            }
+            print_geo_stats(&geo);
+        }
+        Commands::Analyze { input, args } => {
+            if !input.exists() {
+                return Err(Error::NotFound(input));
+            }
+            let (geo, options, pool) = prepare_analysis(args)?;
+            let start_time = Instant::now();
 
-                println!("程序总耗时: {:.2?}", start_time.elapsed());
+            let path = input.to_string_lossy();
+            if input.is_file() {
+                let reports = pool.install(|| analyze::analyze_single_file(&path, &geo, &options))?;
+                print_reports(&reports, "✅ 分析完成");
+            } else if input.is_dir() {
+                let pb = file_progress(&path)?;
+                let reports = pool.install(|| {
+                    analyze::analyze_directory(&path, &geo, &options, &|file| {
+                        pb.set_message(format!("已完成: {}", file.display()));
+                        pb.inc(1);
+                    })
+                })?;
+                pb.finish_with_message("全部文件分析完成");
+                print_reports(&reports, "✅ 所有文件分析完成");
             } else {
-                eprintln!("❌ 参数错误！");
-                print_usage();
-                std::process::exit(1);
+                return Err(Error::Config(format!("无法识别输入路径类型: {}", path)));
             }
+
+            println!("程序总耗时: {:.2?}", start_time.elapsed());
+            print_geo_stats(&geo);
+        }
+        Commands::Merge { dir, args } => {
+            let (geo, options, pool) = prepare_analysis(args)?;
+            let start_time = Instant::now();
+
+            let path = dir.to_string_lossy();
+            let pb = file_progress(&path)?;
+            let reports = pool.install(|| {
+                analyze::analyze_directory_merged(&path, &geo, &options, &|file| {
+                    pb.set_message(format!("已完成: {}", file.display()));
+                    pb.inc(1);
+                })
+            })?;
+            pb.finish_with_message("全部文件分析完成");
+            print_reports(&reports, "✅ 所有文件分析完成");
+
+            println!("程序总耗时: {:.2?}", start_time.elapsed());
+            print_geo_stats(&geo);
         }
-        _ => {
-            eprintln!("❌ 参数错误！");
-            print_usage();
-            std::process::exit(1);
+        Commands::Completions { shell } => {
+            clap_complete::generate(shell, &mut Cli::command(), "PcapRacer", &mut std::io::stdout());
         }
     }
 
-    fs::remove_file("temp_output.tsv")?;
     Ok(())
 }
 
-fn print_usage() {
-    println!("╔══════════════════════════════════════════════════════════╗");
-    println!("║            🚀 PcapPracer 流量分析统计工具                ║");
-    println!("╠══════════════════════════════════════════════════════════╣");
-    println!("║ 用法:                                                    ║");
-    // 设置宽度，左对齐
-    println!("║   {:<55}║", format!("PcapRacer.exe -h | --help"));
-    println!("║   {:<55}║", format!("PcapRacer.exe -v | --version"));
-    println!("║   {:<55}║", format!("PcapRacer.exe -i <input_ip>"));
-    println!("║   {:<55}║", format!("PcapRacer.exe -f <input_pcap> | <input_pcap>"));
-    println!("║   {:<55}║", format!("PcapRacer.exe -F <input_Dir> | <input_Dir>"));
-    println!("║   {:<55}║", format!("PcapRacer.exe -F -A <input_Dir>"));
-    println!("║                                                          ║");
-    println!("║    输出的 CSV 文件名默认为 源文件/源文件名.csv           ║");
-    println!("║                                                          ║");
-    println!("║ 参数说明:                                                ║");
-    println!("║   -i                                                     ║");
-    println!("║         <input_ip>       对单个ip进行地理位置查询        ║");
-    println!("║   [-f]                                                   ║");
-    println!("║         <input_pcap>     要分析的 pcap 文件路径 (必需)   ║");
-    println!("║   [-F]                                                   ║");
-    println!("║         <input_Dir>     要分析的 pcap 文件夹路径 (必需)  ║");
-    println!("║         -A <input_Dir>  要分析的 pcap 文件夹路径 (必需)  ║");
-    println!("║                         并将结果汇总成一个文件           ║");
-    println!("║   [-c]                                                   ║");
-    println!("║         <company>       仅保留域名中包含company的行      ║");
-    println!("║                                                          ║");
-    println!("║                      注意:此项将分析文件夹内所有pcap文件 ║");
-    println!("║                                                          ║");
-    println!("║   -h, --help             显示帮助信息并退出              ║");
-    println!("║                                                          ║");
-    println!("║   -v, --version          显示版本                        ║");
-    println!("║                                     create by: wakamda   ║");
-    println!("╚══════════════════════════════════════════════════════════╝");
-}
-
-fn check_tshark() -> bool {
-    match Command::new("tshark").arg("--version").output() {
-        Ok(output) => {
-            if output.status.success() {
-                let version = String::from_utf8_lossy(&output.stdout);
-                println!("检测到 tshark 版本：{}", version.lines().next().unwrap_or("未知"));
-                true
-            } else {
-                eprintln!("❌ tshark 检查失败，请确保已安装 tshark(wireshark) 并在 PATH 中可用。");
-                false
+// 目录分析的进度条，长度为目录中的抓包文件数
+fn file_progress(dir_path: &str) -> Result<ProgressBar> {
+    let files = analyze::find_capture_files(dir_path)?;
+    let pb = ProgressBar::new(files.len() as u64);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} {msg}")
+            .unwrap()
+            .progress_chars("#>-"),
+    );
+    Ok(pb)
+}
+
+fn print_reports(reports: &[Report], done: &str) {
+    for report in reports {
+        println!("⏳ 本机地址: {}，远端 IP {} 个", report.local, report.rows);
+        println!("{}，结果已保存到 {}", done, report.output.display());
+    }
+}
+
+fn print_geo_stats(geo: &GeoSource) {
+    let stats = geo.stats();
+    if let Some((hits, misses)) = stats.cache {
+        println!("🗂️ 归属地缓存: 命中 {} 个，未命中 {} 个", hits, misses);
+    }
+    if stats.failed > 0 {
+        eprintln!("⚠️ 归属地查询失败 {} 个 IP（已重试），这些 IP 的归属地标记为未知", stats.failed);
+    }
+}
+
+// 按命令行参数准备归属地来源、分析选项和并发分析文件的线程池
+fn prepare_analysis(args: AnalyzeArgs) -> Result<(GeoSource, AnalyzeOptions, rayon::ThreadPool)> {
+    // 归属地来源：GEO_PROVIDER 指定，未指定时 GEO_DB_PATH 离线库优先，其次 API_URL
+    let geo = if args.no_geo {
+        GeoSource::none()
+    } else {
+        GeoSource::from_env()?
+    };
+
+    if let Some(name) = geo.provider_name() {
+        println!("🌐 归属地来源: {}", name);
+    }
+    if !geo.is_available() && !args.no_geo {
+        eprintln!("⚠️ 未配置归属地来源，位置信息将无法查询，归属地列将标记为不可用。请在 .env 文件中设置 GEO_PROVIDER、API_URL 或离线库路径 GEO_DB_PATH。");
+    }
+
+    let mut filter = Filter::default();
+    for spec in &args.filter {
+        filter.include(spec)?;
+    }
+    for spec in &args.exclude {
+        filter.exclude(spec)?;
+    }
+
+    // 未指定线程数时为 0，由 rayon 按 CPU 核数决定
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.threads.map_or(0, usize::from))
+        .build()
+        .map_err(|e| Error::Config(format!("无法创建线程池: {}", e)))?;
+
+    let backend = if args.tshark { Backend::Tshark } else { Backend::Native };
+    if backend == Backend::Tshark {
+        check_tshark()?;
+    }
+
+    // 可读格式本身就是 human，追加的可读格式列没有意义
+    if args.human_bytes && args.bytes_unit == Some(ByteUnit::Human) {
+        return Err(Error::Config("--human-bytes 不能与 --bytes-unit human 同时使用，请指定 bytes、kb、mb 或 gb".to_string()));
+    }
+
+    let options = AnalyzeOptions {
+        output: args.output,
+        format: args.format,
+        min_bytes: args.min_bytes,
+        filter,
+        backend,
+        interval: args.interval,
+        local: args.local_ip,
+        multi_host: args.multi_host,
+        net: NetConfig::from_env()?,
+        csv: CsvOptions {
+            legacy_location: args.legacy_location,
+            port_details: args.ports,
+            flows: args.flows,
+            domains: args.domains,
+            bytes: ByteFormat {
+                unit: args.bytes_unit.unwrap_or_default(),
+                base: args.unit_base,
+                human_column: args.human_bytes,
+            },
+            columns: args.columns,
+            sort: args.sort,
+            domain_separator: args.join_domains,
+        },
+    };
+    Ok((geo, options, pool))
+}
+
+// 兼容旧版参数：-i <ip>、-f <文件>、-F <目录>、-F -A <目录> 以及直接给出路径，转换为对应的子命令
+fn translate_legacy_args(mut args: Vec<String>) -> Vec<String> {
+    if let Some(pos) = args.iter().position(|a| a == "-i" || a == "-f" || a == "-F") {
+        let command = match args[pos].as_str() {
+            "-i" => "lookup",
+            "-f" => "analyze",
+            _ if args.get(pos + 1).is_some_and(|a| a == "-A") => {
+                args.remove(pos + 1);
+                "merge"
             }
+            _ => "analyze",
+        };
+        // 子命令需位于其他参数之前
+        args.remove(pos);
+        args.insert(1, command.to_string());
+    } else if args.len() > 1 && !args[1].starts_with('-') && Cli::command().find_subcommand(&args[1]).is_none() && Path::new(&args[1]).exists() {
+        args.insert(1, "analyze".to_string());
+    }
+    args
+}
+
+fn parse_interval_arg(value: &str) -> std::result::Result<u64, String> {
+    parse_interval(value).ok_or_else(|| "需要一个时间间隔，如 60、30s、5m、1h".to_string())
+}
+
+fn parse_unit_base(value: &str) -> std::result::Result<u64, String> {
+    match value.trim() {
+        "1000" => Ok(1000),
+        "1024" => Ok(1024),
+        _ => Err("换算进制只能为 1000 或 1024".to_string()),
+    }
+}
+
+// 解析时间间隔：纯数字为秒，支持 s/m/h 后缀，必须大于 0
+fn parse_interval(value: &str) -> Option<u64> {
+    let value = value.trim().to_lowercase();
+    let (number, unit) = match value.char_indices().last()? {
+        (i, 's') => (&value[..i], 1),
+        (i, 'm') => (&value[..i], 60),
+        (i, 'h') => (&value[..i], 3600),
+        _ => (value.as_str(), 1),
+    };
+    let secs = number.parse::<u64>().ok()?.checked_mul(unit)?;
+    (secs > 0).then_some(secs)
+}
+
+fn check_tshark() -> Result<()> {
+    match Command::new("tshark").arg("--version").output() {
+        Ok(output) if output.status.success() => {
+            let version = String::from_utf8_lossy(&output.stdout);
+            println!("检测到 tshark 版本：{}", version.lines().next().unwrap_or("未知"));
+            Ok(())
         }
-        Err(_) => {
-            eprintln!("❌ 无法执行 tshark，请确保已安装 tshark(wireshark) 并在 PATH 中可用。");
-            false
-        }
+        Ok(_) => Err(Error::Tshark("tshark 检查失败，请确保已安装 tshark(wireshark) 并在 PATH 中可用。".to_string())),
+        Err(_) => Err(Error::Tshark("无法执行 tshark，请确保已安装 tshark(wireshark) 并在 PATH 中可用。".to_string())),
     }
 }
 
@@ -0,0 +1,79 @@
+use crate::error::Result;
+use crate::pcap;
+use crate::tshark;
+use std::net::{IpAddr, Ipv4Addr};
+
+/// 单个数据包中参与统计的字段，与 tshark 导出的列一一对应
+#[derive(Debug, Default, Clone)]
+pub struct PacketRecord {
+    /// 抓包时间（Unix 秒），没有时间戳的数据包为 0
+    pub timestamp: f64,
+    /// 以太网源/目的 MAC，非以太网链路为 None
+    pub src_mac: Option<[u8; 6]>,
+    pub dst_mac: Option<[u8; 6]>,
+    /// 非 IP 数据包为 None
+    pub src: Option<IpAddr>,
+    pub dst: Option<IpAddr>,
+    /// IP 包总长度（IPv6 为负载长度加 40 字节固定头）
+    pub len: u64,
+    /// IP 协议号（IPv6 为跳过扩展头后的下一个头），未知时为 0
+    pub proto: u8,
+    /// TCP/UDP 端口，其他协议为 0
+    pub src_port: u16,
+    pub dst_port: u16,
+    /// TCP 标志位（低 8 位），非 TCP 为 0
+    pub tcp_flags: u8,
+    /// DHCP 应答中的默认网关（option 3）
+    pub dhcp_router: Option<Ipv4Addr>,
+    pub dns_name: String,
+    /// DNS 应答中的 A/AAAA/CNAME 记录
+    pub dns_answers: Vec<DnsAnswer>,
+    pub http_host: String,
+    pub sni: String,
+}
+
+/// DNS 应答中的一条资源记录
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DnsAnswer {
+    /// A/AAAA 记录：域名解析到的地址
+    Addr(String, IpAddr),
+    /// CNAME 记录：别名指向的规范名
+    Cname(String, String),
+}
+
+/// 抓包文件解析后端
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// 内置的 pcap/pcapng 解析器，无需安装 Wireshark
+    Native,
+    /// 调用外部 tshark 进行解析
+    Tshark,
+}
+
+/// 使用指定后端流式读取抓包文件，每个数据包回调一次
+pub fn for_each_packet(
+    input_pcap: &str,
+    backend: Backend,
+    on_packet: &mut dyn FnMut(PacketRecord),
+) -> Result<()> {
+    match backend {
+        Backend::Native => pcap::read_pcap_file(input_pcap, on_packet),
+        Backend::Tshark => tshark::run_tshark(input_pcap, on_packet),
+    }
+}
+
+/// 解析 aa:bb:cc:dd:ee:ff 或 aa-bb-cc-dd-ee-ff 形式的 MAC 地址
+pub fn parse_mac(text: &str) -> Option<[u8; 6]> {
+    let parts: Vec<&str> = text.trim().split([':', '-']).collect();
+    if parts.len() != 6 {
+        return None;
+    }
+    let mut mac = [0u8; 6];
+    for (byte, part) in mac.iter_mut().zip(parts) {
+        if part.len() != 2 {
+            return None;
+        }
+        *byte = u8::from_str_radix(part, 16).ok()?;
+    }
+    Some(mac)
+}
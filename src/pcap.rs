@@ -0,0 +1,1210 @@
+use crate::error::{Error, Result};
+use crate::packet::{DnsAnswer, PacketRecord};
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+// 链路层类型（LINKTYPE_*）
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+// 以太网类型
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+const ETHERTYPE_VLAN: [u16; 3] = [0x8100, 0x88A8, 0x9100];
+
+// IP 协议号
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+
+// DNS 资源记录类型
+const DNS_TYPE_A: usize = 1;
+const DNS_TYPE_CNAME: usize = 5;
+const DNS_TYPE_AAAA: usize = 28;
+
+// DHCP
+const DHCP_MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+const DHCP_OPT_PAD: u8 = 0;
+const DHCP_OPT_ROUTER: u8 = 3;
+const DHCP_OPT_END: u8 = 255;
+
+// IPv6 扩展头
+const IPV6_EXT_HOP_BY_HOP: u8 = 0;
+const IPV6_EXT_ROUTING: u8 = 43;
+const IPV6_EXT_FRAGMENT: u8 = 44;
+const IPV6_EXT_AUTH: u8 = 51;
+const IPV6_EXT_DEST_OPTS: u8 = 60;
+
+// pcapng 块类型
+const PCAPNG_SHB: [u8; 4] = [0x0A, 0x0D, 0x0D, 0x0A];
+const PCAPNG_IDB: u32 = 1;
+const PCAPNG_PB: u32 = 2;
+const PCAPNG_SPB: u32 = 3;
+const PCAPNG_EPB: u32 = 6;
+
+// pcapng 接口选项
+const PCAPNG_OPT_END: u16 = 0;
+const PCAPNG_OPT_IF_TSRESOL: u16 = 9;
+const PCAPNG_OPT_IF_TSOFFSET: u16 = 14;
+
+// 单个数据包/块的长度上限，超过即视为文件损坏
+const MAX_BLOCK_LEN: usize = 256 * 1024 * 1024;
+
+const HTTP_METHODS: [&[u8]; 9] = [
+    b"GET ", b"POST ", b"PUT ", b"HEAD ", b"DELETE ",
+    b"OPTIONS ", b"PATCH ", b"CONNECT ", b"TRACE ",
+];
+
+/// 读取 pcap 或 pcapng 文件，每解析出一个 IP 数据包就回调一次
+pub fn read_pcap_file(
+    path: &str,
+    on_packet: &mut dyn FnMut(PacketRecord),
+) -> Result<()> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+
+    match magic {
+        // 微秒 / 纳秒精度的经典 pcap
+        [0xD4, 0xC3, 0xB2, 0xA1] => read_classic(reader, true, 1e6, on_packet),
+        [0x4D, 0x3C, 0xB2, 0xA1] => read_classic(reader, true, 1e9, on_packet),
+        [0xA1, 0xB2, 0xC3, 0xD4] => read_classic(reader, false, 1e6, on_packet),
+        [0xA1, 0xB2, 0x3C, 0x4D] => read_classic(reader, false, 1e9, on_packet),
+        PCAPNG_SHB => read_pcapng(reader, on_packet),
+        _ => Err(Error::Capture(format!("无法识别的抓包文件格式: {}", path))),
+    }
+}
+
+fn read_classic<R: Read>(
+    mut reader: R,
+    le: bool,
+    // 时间戳小数部分的单位：每秒 1e6（微秒）或 1e9（纳秒）
+    frac_per_sec: f64,
+    on_packet: &mut dyn FnMut(PacketRecord),
+) -> Result<()> {
+    // 全局头剩余 20 字节，最后 4 字节为链路层类型（高位为 FCS 信息）
+    let mut header = [0u8; 20];
+    reader.read_exact(&mut header)?;
+    let linktype = read_u32(&header[16..20], le) & 0x0FFF_FFFF;
+
+    let mut record_header = [0u8; 16];
+    let mut data = Vec::new();
+
+    loop {
+        if !read_full(&mut reader, &mut record_header)? {
+            break;
+        }
+        let incl_len = read_u32(&record_header[8..12], le) as usize;
+        if incl_len > MAX_BLOCK_LEN {
+            return Err(Error::Capture(format!("数据包长度异常({} 字节)，文件可能已损坏", incl_len)));
+        }
+
+        data.resize(incl_len, 0);
+        if !read_full(&mut reader, &mut data)? {
+            break;
+        }
+
+        if let Some(mut record) = decode_packet(linktype, &data) {
+            let ts_sec = read_u32(&record_header[..4], le) as f64;
+            let ts_frac = read_u32(&record_header[4..8], le) as f64;
+            record.timestamp = ts_sec + ts_frac / frac_per_sec;
+            on_packet(record);
+        }
+    }
+
+    Ok(())
+}
+
+fn read_pcapng<R: Read>(
+    mut reader: R,
+    on_packet: &mut dyn FnMut(PacketRecord),
+) -> Result<()> {
+    let mut le = true;
+    // 当前 section 中的各接口，下标即接口 ID
+    let mut interfaces: Vec<Interface> = Vec::new();
+
+    // 首个块类型已在识别格式时读取
+    let mut head = [0u8; 8];
+    head[..4].copy_from_slice(&PCAPNG_SHB);
+    reader.read_exact(&mut head[4..])?;
+
+    let mut body = Vec::new();
+
+    loop {
+        if head[..4] == PCAPNG_SHB {
+            // Section Header Block 的字节序由其后的 magic 决定
+            let mut bom = [0u8; 4];
+            reader.read_exact(&mut bom)?;
+            le = match bom {
+                [0x4D, 0x3C, 0x2B, 0x1A] => true,
+                [0x1A, 0x2B, 0x3C, 0x4D] => false,
+                _ => return Err(Error::Capture("pcapng 字节序标识无效，文件可能已损坏".to_string())),
+            };
+            let total_len = read_u32(&head[4..8], le) as usize;
+            if !(12..=MAX_BLOCK_LEN).contains(&total_len) {
+                return Err(Error::Capture(format!("pcapng 块长度异常({} 字节)，文件可能已损坏", total_len)));
+            }
+            io::copy(&mut reader.by_ref().take((total_len - 12) as u64), &mut io::sink())?;
+            interfaces.clear();
+        } else {
+            let block_type = read_u32(&head[..4], le);
+            let total_len = read_u32(&head[4..8], le) as usize;
+            if !(12..=MAX_BLOCK_LEN).contains(&total_len) {
+                return Err(Error::Capture(format!("pcapng 块长度异常({} 字节)，文件可能已损坏", total_len)));
+            }
+
+            // 块体（含末尾 4 字节长度）
+            body.resize(total_len - 8, 0);
+            if !read_full(&mut reader, &mut body)? {
+                break;
+            }
+            let body = &body[..body.len() - 4];
+
+            match block_type {
+                PCAPNG_IDB if body.len() >= 2 => {
+                    interfaces.push(Interface::parse(body, le));
+                }
+                PCAPNG_EPB if body.len() >= 20 => {
+                    let iface = read_u32(&body[..4], le) as usize;
+                    let cap_len = read_u32(&body[12..16], le) as usize;
+                    let data = &body[20..body.len().min(20 + cap_len)];
+                    if let Some(iface) = interfaces.get(iface) {
+                        if let Some(mut record) = decode_packet(iface.linktype, data) {
+                            record.timestamp = iface.timestamp(&body[4..12], le);
+                            on_packet(record);
+                        }
+                    }
+                }
+                PCAPNG_SPB if body.len() >= 4 => {
+                    // Simple Packet Block 固定属于接口 0，没有时间戳
+                    let orig_len = read_u32(&body[..4], le) as usize;
+                    let data = &body[4..body.len().min(4 + orig_len)];
+                    if let Some(record) = interfaces.first().and_then(|iface| decode_packet(iface.linktype, data)) {
+                        on_packet(record);
+                    }
+                }
+                PCAPNG_PB if body.len() >= 20 => {
+                    // 已废弃的 Packet Block，接口 ID 只有 2 字节
+                    let iface = read_u16(&body[..2], le) as usize;
+                    let cap_len = read_u32(&body[12..16], le) as usize;
+                    let data = &body[20..body.len().min(20 + cap_len)];
+                    if let Some(iface) = interfaces.get(iface) {
+                        if let Some(mut record) = decode_packet(iface.linktype, data) {
+                            record.timestamp = iface.timestamp(&body[4..12], le);
+                            on_packet(record);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if !read_full(&mut reader, &mut head)? {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// pcapng 接口描述：链路层类型与时间戳精度
+struct Interface {
+    linktype: u32,
+    // 时间戳每秒的单位数，默认微秒
+    units_per_sec: f64,
+    // if_tsoffset：时间戳需加上的秒数
+    offset_secs: f64,
+}
+
+impl Interface {
+    fn parse(body: &[u8], le: bool) -> Self {
+        let mut iface = Interface {
+            linktype: read_u16(&body[..2], le) as u32,
+            units_per_sec: 1e6,
+            offset_secs: 0.0,
+        };
+
+        // 选项从第 8 字节开始：2 字节代码 + 2 字节长度 + 按 4 字节对齐的值
+        let mut pos = 8;
+        while pos + 4 <= body.len() {
+            let code = read_u16(&body[pos..], le);
+            let len = read_u16(&body[pos + 2..], le) as usize;
+            let Some(value) = body.get(pos + 4..pos + 4 + len) else {
+                break;
+            };
+            match code {
+                PCAPNG_OPT_END => break,
+                PCAPNG_OPT_IF_TSRESOL if len >= 1 => {
+                    // 最高位为 0 表示 10 的负幂，为 1 表示 2 的负幂
+                    let exp = (value[0] & 0x7F) as i32;
+                    iface.units_per_sec = if value[0] & 0x80 == 0 { 10f64.powi(exp) } else { 2f64.powi(exp) };
+                }
+                PCAPNG_OPT_IF_TSOFFSET if len >= 8 => {
+                    let high = read_u32(&value[..4], le) as u64;
+                    let low = read_u32(&value[4..8], le) as u64;
+                    iface.offset_secs = ((high << 32) | low) as i64 as f64;
+                }
+                _ => {}
+            }
+            pos += 4 + len.div_ceil(4) * 4;
+        }
+        iface
+    }
+
+    // EPB/PB 中的 64 位时间戳分为高、低两个 32 位
+    fn timestamp(&self, ts: &[u8], le: bool) -> f64 {
+        let high = read_u32(&ts[..4], le) as u64;
+        let low = read_u32(&ts[4..8], le) as u64;
+        ((high << 32) | low) as f64 / self.units_per_sec + self.offset_secs
+    }
+}
+
+/// 尽量读满缓冲区。文件正常结束返回 false；文件被截断时给出警告并同样返回 false
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    if filled == buf.len() {
+        Ok(true)
+    } else {
+        if filled > 0 {
+            log::warn!("抓包文件在数据包中间被截断，已忽略最后一个不完整的数据包");
+        }
+        Ok(false)
+    }
+}
+
+fn read_u16(bytes: &[u8], le: bool) -> u16 {
+    let b = [bytes[0], bytes[1]];
+    if le { u16::from_le_bytes(b) } else { u16::from_be_bytes(b) }
+}
+
+fn read_u32(bytes: &[u8], le: bool) -> u32 {
+    let b = [bytes[0], bytes[1], bytes[2], bytes[3]];
+    if le { u32::from_le_bytes(b) } else { u32::from_be_bytes(b) }
+}
+
+/// 读取网络字节序的 16 位整数，越界时返回 None
+fn be16(data: &[u8], pos: usize) -> Option<usize> {
+    let b = data.get(pos..pos + 2)?;
+    Some(u16::from_be_bytes([b[0], b[1]]) as usize)
+}
+
+fn decode_packet(linktype: u32, data: &[u8]) -> Option<PacketRecord> {
+    let (ethertype, payload) = match linktype {
+        LINKTYPE_ETHERNET => strip_ethernet(data)?,
+        // BSD loopback 的协议族字段字节序不定，直接看 IP 版本号
+        LINKTYPE_NULL => ip_version_ethertype(data.get(4..)?)?,
+        LINKTYPE_RAW | LINKTYPE_IPV4 => ip_version_ethertype(data)?,
+        LINKTYPE_LINUX_SLL => (be16(data, 14)? as u16, data.get(16..)?),
+        LINKTYPE_LINUX_SLL2 => (be16(data, 0)? as u16, data.get(20..)?),
+        _ => return None,
+    };
+
+    let mut record = match ethertype {
+        ETHERTYPE_IPV4 => parse_ipv4(payload)?,
+        ETHERTYPE_IPV6 => parse_ipv6(payload)?,
+        _ => return None,
+    };
+
+    if linktype == LINKTYPE_ETHERNET {
+        record.dst_mac = data.get(0..6).and_then(|m| m.try_into().ok());
+        record.src_mac = data.get(6..12).and_then(|m| m.try_into().ok());
+    }
+    Some(record)
+}
+
+/// 对没有链路层类型字段的帧，按 IP 版本号推断协议
+fn ip_version_ethertype(data: &[u8]) -> Option<(u16, &[u8])> {
+    match data.first()? >> 4 {
+        4 => Some((ETHERTYPE_IPV4, data)),
+        6 => Some((ETHERTYPE_IPV6, data)),
+        _ => None,
+    }
+}
+
+/// 去掉以太网头（及 VLAN 标签），返回上层协议类型和负载
+fn strip_ethernet(data: &[u8]) -> Option<(u16, &[u8])> {
+    let mut offset = 12;
+    let mut ethertype = be16(data, offset)? as u16;
+    while ETHERTYPE_VLAN.contains(&ethertype) {
+        offset += 4;
+        ethertype = be16(data, offset)? as u16;
+    }
+    Some((ethertype, data.get(offset + 2..)?))
+}
+
+fn parse_ipv4(data: &[u8]) -> Option<PacketRecord> {
+    if data.len() < 20 || data[0] >> 4 != 4 {
+        return None;
+    }
+    let ihl = (data[0] & 0x0F) as usize * 4;
+    if ihl < 20 || data.len() < ihl {
+        return None;
+    }
+
+    let total_len = be16(data, 2)?;
+    let frag_offset = be16(data, 6)? & 0x1FFF;
+    let proto = data[9];
+    let src = Ipv4Addr::new(data[12], data[13], data[14], data[15]);
+    let dst = Ipv4Addr::new(data[16], data[17], data[18], data[19]);
+
+    let mut record = PacketRecord {
+        src: Some(IpAddr::V4(src)),
+        dst: Some(IpAddr::V4(dst)),
+        len: total_len as u64,
+        proto,
+        ..Default::default()
+    };
+
+    // 只有首个分片携带传输层头
+    if frag_offset == 0 {
+        let end = total_len.clamp(ihl, data.len());
+        parse_transport(proto, &data[ihl..end], &mut record);
+    }
+
+    Some(record)
+}
+
+fn parse_ipv6(data: &[u8]) -> Option<PacketRecord> {
+    if data.len() < 40 || data[0] >> 4 != 6 {
+        return None;
+    }
+
+    let payload_len = be16(data, 4)?;
+    let src: [u8; 16] = data[8..24].try_into().ok()?;
+    let dst: [u8; 16] = data[24..40].try_into().ok()?;
+
+    // 与 IPv4 的 ip.len 口径一致，长度包含 40 字节固定头
+    let mut record = PacketRecord {
+        src: Some(IpAddr::V6(Ipv6Addr::from(src))),
+        dst: Some(IpAddr::V6(Ipv6Addr::from(dst))),
+        len: payload_len as u64 + 40,
+        ..Default::default()
+    };
+
+    let end = (40 + payload_len).min(data.len());
+    let mut next_header = data[6];
+    let mut pos = 40;
+
+    // 跳过扩展头，找到传输层；扩展头被截断或长度越界时仍统计该包，只是不解析传输层
+    while matches!(
+        next_header,
+        IPV6_EXT_HOP_BY_HOP | IPV6_EXT_ROUTING | IPV6_EXT_DEST_OPTS | IPV6_EXT_AUTH | IPV6_EXT_FRAGMENT
+    ) {
+        let Some(&[next, hdr_len]) = data.get(pos..pos + 2) else {
+            return Some(record);
+        };
+        match next_header {
+            IPV6_EXT_AUTH => pos += (hdr_len as usize + 2) * 4,
+            IPV6_EXT_FRAGMENT => {
+                // 非首个分片不含传输层头
+                if be16(data, pos + 2).is_none_or(|offset| offset & 0xFFF8 != 0) {
+                    record.proto = next;
+                    return Some(record);
+                }
+                pos += 8;
+            }
+            _ => pos += (hdr_len as usize + 1) * 8,
+        }
+        next_header = next;
+        if pos > end {
+            return Some(record);
+        }
+    }
+
+    record.proto = next_header;
+    parse_transport(next_header, &data[pos..end], &mut record);
+    Some(record)
+}
+
+fn parse_transport(proto: u8, data: &[u8], record: &mut PacketRecord) {
+    match proto {
+        IPPROTO_UDP => {
+            let (Some(sport), Some(dport)) = (be16(data, 0), be16(data, 2)) else {
+                return;
+            };
+            record.src_port = sport as u16;
+            record.dst_port = dport as u16;
+            if is_dns_port(sport) || is_dns_port(dport) {
+                if let Some(msg) = data.get(8..) {
+                    parse_dns(msg, record);
+                }
+            } else if sport == 67 && dport == 68 {
+                record.dhcp_router = data.get(8..).and_then(parse_dhcp_router);
+            }
+        }
+        IPPROTO_TCP => {
+            let (Some(sport), Some(dport)) = (be16(data, 0), be16(data, 2)) else {
+                return;
+            };
+            record.src_port = sport as u16;
+            record.dst_port = dport as u16;
+            record.tcp_flags = data.get(13).copied().unwrap_or(0);
+            let Some(&offset_byte) = data.get(12) else {
+                return;
+            };
+            let offset = (offset_byte >> 4) as usize * 4;
+            let Some(payload) = data.get(offset..) else {
+                return;
+            };
+            if offset < 20 || payload.is_empty() {
+                return;
+            }
+
+            if sport == 53 || dport == 53 {
+                // TCP 上的 DNS 报文前有 2 字节长度
+                if let Some(msg) = payload.get(2..) {
+                    parse_dns(msg, record);
+                }
+            } else if let Some(host) = parse_http_host(payload) {
+                record.http_host = host;
+            } else if let Some(sni) = parse_tls_sni(payload) {
+                record.sni = sni;
+            }
+        }
+        _ => {}
+    }
+}
+
+/// 取 DHCP 应答（BOOTREPLY）中 option 3 的第一个路由器地址
+fn parse_dhcp_router(msg: &[u8]) -> Option<Ipv4Addr> {
+    // 236 字节固定头后为 magic cookie，其后是选项
+    if *msg.first()? != 2 || msg.get(236..240)? != DHCP_MAGIC_COOKIE {
+        return None;
+    }
+    let mut pos = 240;
+    while let Some(&code) = msg.get(pos) {
+        match code {
+            DHCP_OPT_PAD => pos += 1,
+            DHCP_OPT_END => break,
+            _ => {
+                let len = *msg.get(pos + 1)? as usize;
+                if code == DHCP_OPT_ROUTER && len >= 4 {
+                    let addr: [u8; 4] = msg.get(pos + 2..pos + 6)?.try_into().ok()?;
+                    return Some(Ipv4Addr::from(addr));
+                }
+                pos += 2 + len;
+            }
+        }
+    }
+    None
+}
+
+fn is_dns_port(port: usize) -> bool {
+    // 53: DNS, 5353: mDNS, 5355: LLMNR，tshark 均按 dns 字段解析
+    matches!(port, 53 | 5353 | 5355)
+}
+
+fn parse_dns(msg: &[u8], record: &mut PacketRecord) {
+    if let Some(name) = parse_dns_qname(msg) {
+        record.dns_name = name;
+    }
+    record.dns_answers = parse_dns_answers(msg).unwrap_or_default();
+}
+
+/// 取 DNS 应答中回答区的 A/AAAA/CNAME 记录，解析出错时返回已读到的部分
+fn parse_dns_answers(msg: &[u8]) -> Option<Vec<DnsAnswer>> {
+    // 只处理应答（QR 位为 1）
+    if msg.len() < 12 || msg[2] & 0x80 == 0 {
+        return None;
+    }
+    let qdcount = be16(msg, 4)?;
+    let ancount = be16(msg, 6)?;
+
+    // 跳过问题区：域名 + 类型 + 类
+    let mut pos = 12;
+    for _ in 0..qdcount {
+        pos = read_dns_name(msg, pos)?.1 + 4;
+    }
+
+    let mut answers = Vec::new();
+    for _ in 0..ancount {
+        let Some((name, next)) = read_dns_name(msg, pos) else {
+            break;
+        };
+        // 类型(2) 类(2) TTL(4) 数据长度(2)
+        let (Some(rtype), Some(rdlen)) = (be16(msg, next), be16(msg, next + 8)) else {
+            break;
+        };
+        let rdata_pos = next + 10;
+        let Some(rdata) = msg.get(rdata_pos..rdata_pos + rdlen) else {
+            break;
+        };
+        match (rtype, rdlen) {
+            (DNS_TYPE_A, 4) => {
+                let addr: [u8; 4] = rdata.try_into().ok()?;
+                answers.push(DnsAnswer::Addr(name, IpAddr::V4(Ipv4Addr::from(addr))));
+            }
+            (DNS_TYPE_AAAA, 16) => {
+                let addr: [u8; 16] = rdata.try_into().ok()?;
+                answers.push(DnsAnswer::Addr(name, IpAddr::V6(Ipv6Addr::from(addr))));
+            }
+            (DNS_TYPE_CNAME, _) => {
+                if let Some((target, _)) = read_dns_name(msg, rdata_pos) {
+                    answers.push(DnsAnswer::Cname(name, target));
+                }
+            }
+            _ => {}
+        }
+        pos = rdata_pos + rdlen;
+    }
+    Some(answers)
+}
+
+/// 取 DNS 报文中的第一个查询名
+fn parse_dns_qname(msg: &[u8]) -> Option<String> {
+    let qdcount = be16(msg, 4)?;
+    if qdcount == 0 || msg.len() < 12 {
+        return None;
+    }
+    read_dns_name(msg, 12).map(|(name, _)| name)
+}
+
+/// 从 pos 处读取一个（可能带压缩指针的）域名，返回域名和其后的位置
+fn read_dns_name(msg: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut next = None;
+    let mut jumps = 0;
+
+    loop {
+        let len = *msg.get(pos)? as usize;
+        if len == 0 {
+            pos += 1;
+            break;
+        }
+        if len & 0xC0 == 0xC0 {
+            // 压缩指针，限制跳转次数防止循环引用
+            let ptr = ((len & 0x3F) << 8) | *msg.get(pos + 1)? as usize;
+            if next.is_none() {
+                next = Some(pos + 2);
+            }
+            jumps += 1;
+            if jumps > 32 {
+                return None;
+            }
+            pos = ptr;
+            continue;
+        }
+        if len & 0xC0 != 0 {
+            return None;
+        }
+        let label = msg.get(pos + 1..pos + 1 + len)?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        pos += 1 + len;
+    }
+
+    Some((labels.join("."), next.unwrap_or(pos)))
+}
+
+/// 从 HTTP 请求中提取 Host 头
+fn parse_http_host(payload: &[u8]) -> Option<String> {
+    if !HTTP_METHODS.iter().any(|m| payload.starts_with(m)) {
+        return None;
+    }
+
+    let text = String::from_utf8_lossy(payload);
+    for line in text.split("\r\n").skip(1) {
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("host") {
+                return Some(value.trim().to_string());
+            }
+        }
+    }
+    None
+}
+
+/// 从 TLS ClientHello 中提取 SNI
+fn parse_tls_sni(payload: &[u8]) -> Option<String> {
+    // 记录头：类型 0x16(握手) + 版本 0x03xx + 长度；握手类型 0x01 为 ClientHello
+    if payload.len() < 9 || payload[0] != 0x16 || payload[1] != 0x03 || payload[5] != 0x01 {
+        return None;
+    }
+
+    // 跳过记录头(5)和握手头(4)
+    let hello = &payload[9..];
+    // client_version(2) + random(32)
+    let mut pos = 34;
+    pos += 1 + *hello.get(pos)? as usize; // session_id
+    pos += 2 + be16(hello, pos)?; // cipher_suites
+    pos += 1 + *hello.get(pos)? as usize; // compression_methods
+
+    let ext_end = (pos + 2 + be16(hello, pos)?).min(hello.len());
+    pos += 2;
+
+    while pos + 4 <= ext_end {
+        let ext_type = be16(hello, pos)?;
+        let ext_len = be16(hello, pos + 2)?;
+        pos += 4;
+
+        if ext_type == 0 {
+            // server_name_list 长度(2) + 类型(1) + 名称长度(2) + 名称
+            let ext = hello.get(pos..pos + ext_len)?;
+            if ext.len() < 5 || ext[2] != 0 {
+                return None;
+            }
+            let name = ext.get(5..5 + be16(ext, 3)?)?;
+            return Some(String::from_utf8_lossy(name).into_owned());
+        }
+        pos += ext_len;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const SRC_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0x01];
+    const DST_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0x02];
+    const LOCAL: [u8; 4] = [192, 168, 1, 10];
+    const REMOTE: [u8; 4] = [93, 184, 216, 34];
+
+    fn u16_bytes(v: u16, le: bool) -> [u8; 2] {
+        if le { v.to_le_bytes() } else { v.to_be_bytes() }
+    }
+
+    fn u32_bytes(v: u32, le: bool) -> [u8; 4] {
+        if le { v.to_le_bytes() } else { v.to_be_bytes() }
+    }
+
+    fn ipv4(proto: u8, src: [u8; 4], dst: [u8; 4], payload: &[u8]) -> Vec<u8> {
+        let total_len = (20 + payload.len()) as u16;
+        let mut data = vec![0x45, 0];
+        data.extend_from_slice(&total_len.to_be_bytes());
+        data.extend_from_slice(&[0, 0, 0, 0, 64, proto, 0, 0]);
+        data.extend_from_slice(&src);
+        data.extend_from_slice(&dst);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn ipv6(next_header: u8, src: &str, dst: &str, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![0x60, 0, 0, 0];
+        data.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        data.extend_from_slice(&[next_header, 64]);
+        data.extend_from_slice(&src.parse::<Ipv6Addr>().unwrap().octets());
+        data.extend_from_slice(&dst.parse::<Ipv6Addr>().unwrap().octets());
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn udp(sport: u16, dport: u16, payload: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&sport.to_be_bytes());
+        data.extend_from_slice(&dport.to_be_bytes());
+        data.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+        data.extend_from_slice(&[0, 0]);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn tcp(sport: u16, dport: u16, payload: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&sport.to_be_bytes());
+        data.extend_from_slice(&dport.to_be_bytes());
+        data.extend_from_slice(&[0; 8]);
+        // 数据偏移 5 个 32 位字，标志 PSH|ACK
+        data.extend_from_slice(&[0x50, 0x18, 0xFF, 0xFF, 0, 0, 0, 0]);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn ethernet(vlans: &[u16], ethertype: u16, payload: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&DST_MAC);
+        data.extend_from_slice(&SRC_MAC);
+        for tpid in vlans {
+            data.extend_from_slice(&tpid.to_be_bytes());
+            data.extend_from_slice(&[0x00, 0x64]);
+        }
+        data.extend_from_slice(&ethertype.to_be_bytes());
+        data.extend_from_slice(payload);
+        data
+    }
+
+    // 经典 pcap：每个数据包为 (秒, 小数部分, 数据)
+    fn classic(le: bool, nanos: bool, linktype: u32, packets: &[(u32, u32, Vec<u8>)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&u32_bytes(if nanos { 0xA1B2_3C4D } else { 0xA1B2_C3D4 }, le));
+        data.extend_from_slice(&u16_bytes(2, le));
+        data.extend_from_slice(&u16_bytes(4, le));
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(&u32_bytes(65535, le));
+        data.extend_from_slice(&u32_bytes(linktype, le));
+        for (sec, frac, packet) in packets {
+            data.extend_from_slice(&u32_bytes(*sec, le));
+            data.extend_from_slice(&u32_bytes(*frac, le));
+            data.extend_from_slice(&u32_bytes(packet.len() as u32, le));
+            data.extend_from_slice(&u32_bytes(packet.len() as u32, le));
+            data.extend_from_slice(packet);
+        }
+        data
+    }
+
+    // pcapng 块：类型 + 总长度 + 按 4 字节对齐的块体 + 总长度
+    fn block(le: bool, block_type: u32, body: &[u8]) -> Vec<u8> {
+        let padded = body.len().div_ceil(4) * 4;
+        let total_len = u32_bytes(12 + padded as u32, le);
+        let mut data = Vec::new();
+        data.extend_from_slice(&u32_bytes(block_type, le));
+        data.extend_from_slice(&total_len);
+        data.extend_from_slice(body);
+        data.resize(8 + padded, 0);
+        data.extend_from_slice(&total_len);
+        data
+    }
+
+    fn shb(le: bool) -> Vec<u8> {
+        let mut body = u32_bytes(0x1A2B_3C4D, le).to_vec();
+        body.extend_from_slice(&u16_bytes(1, le));
+        body.extend_from_slice(&u16_bytes(0, le));
+        body.extend_from_slice(&[0xFF; 8]);
+        block(le, 0x0A0D_0D0A, &body)
+    }
+
+    fn idb(le: bool, linktype: u16, options: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut body = u16_bytes(linktype, le).to_vec();
+        body.extend_from_slice(&[0, 0]);
+        body.extend_from_slice(&u32_bytes(65535, le));
+        for (code, value) in options {
+            body.extend_from_slice(&u16_bytes(*code, le));
+            body.extend_from_slice(&u16_bytes(value.len() as u16, le));
+            body.extend_from_slice(value);
+            body.resize(body.len().div_ceil(4) * 4, 0);
+        }
+        body.extend_from_slice(&[0; 4]);
+        block(le, PCAPNG_IDB, &body)
+    }
+
+    fn epb(le: bool, iface: u32, ts: u64, packet: &[u8]) -> Vec<u8> {
+        let mut body = u32_bytes(iface, le).to_vec();
+        body.extend_from_slice(&u32_bytes((ts >> 32) as u32, le));
+        body.extend_from_slice(&u32_bytes(ts as u32, le));
+        body.extend_from_slice(&u32_bytes(packet.len() as u32, le));
+        body.extend_from_slice(&u32_bytes(packet.len() as u32, le));
+        body.extend_from_slice(packet);
+        block(le, PCAPNG_EPB, &body)
+    }
+
+    fn spb(le: bool, packet: &[u8]) -> Vec<u8> {
+        let mut body = u32_bytes(packet.len() as u32, le).to_vec();
+        body.extend_from_slice(packet);
+        block(le, PCAPNG_SPB, &body)
+    }
+
+    // 写入临时文件后按文件读取，覆盖格式识别
+    fn read_capture(name: &str, bytes: &[u8]) -> Result<Vec<PacketRecord>> {
+        let path = std::env::temp_dir().join(format!("pcapracer-{}-{}", std::process::id(), name));
+        fs::write(&path, bytes).unwrap();
+        let mut records = Vec::new();
+        let result = read_pcap_file(path.to_str().unwrap(), &mut |record| records.push(record));
+        let _ = fs::remove_file(&path);
+        result.map(|_| records)
+    }
+
+    fn decode_ipv4(payload_proto: u8, payload: &[u8]) -> PacketRecord {
+        decode_packet(LINKTYPE_RAW, &ipv4(payload_proto, LOCAL, REMOTE, payload)).unwrap()
+    }
+
+    fn dns_name(name: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        for label in name.split('.') {
+            data.push(label.len() as u8);
+            data.extend_from_slice(label.as_bytes());
+        }
+        data.push(0);
+        data
+    }
+
+    fn client_hello(sni: &str) -> Vec<u8> {
+        let mut hello = vec![0x03, 0x03];
+        hello.extend_from_slice(&[0; 32]);
+        hello.push(32);
+        hello.extend_from_slice(&[0xAB; 32]);
+        hello.extend_from_slice(&[0, 4, 0x13, 0x01, 0x13, 0x02]);
+        hello.extend_from_slice(&[1, 0]);
+
+        let mut server_name = Vec::new();
+        server_name.extend_from_slice(&((sni.len() + 3) as u16).to_be_bytes());
+        server_name.push(0);
+        server_name.extend_from_slice(&(sni.len() as u16).to_be_bytes());
+        server_name.extend_from_slice(sni.as_bytes());
+
+        // SNI 前后各放一个其他扩展
+        let mut extensions = vec![0x00, 0x0B, 0, 2, 1, 0];
+        extensions.extend_from_slice(&[0, 0]);
+        extensions.extend_from_slice(&(server_name.len() as u16).to_be_bytes());
+        extensions.extend_from_slice(&server_name);
+        extensions.extend_from_slice(&[0x00, 0x2B, 0, 3, 2, 0x03, 0x04]);
+        hello.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        hello.extend_from_slice(&extensions);
+
+        let mut handshake = vec![0x01, 0];
+        handshake.extend_from_slice(&(hello.len() as u16).to_be_bytes());
+        handshake.extend_from_slice(&hello);
+        let mut record = vec![0x16, 0x03, 0x01];
+        record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+        record.extend_from_slice(&handshake);
+        record
+    }
+
+    #[test]
+    fn classic_pcap_byte_orders_and_precision() {
+        let packet = ethernet(&[], ETHERTYPE_IPV4, &ipv4(IPPROTO_UDP, LOCAL, REMOTE, &udp(50000, 443, b"quic")));
+        for (le, nanos, frac, expected) in [
+            (true, false, 500_000, 1_700_000_000.5),
+            (false, false, 250_000, 1_700_000_000.25),
+            (true, true, 750_000_000, 1_700_000_000.75),
+            (false, true, 125_000_000, 1_700_000_000.125),
+        ] {
+            let bytes = classic(le, nanos, LINKTYPE_ETHERNET, &[(1_700_000_000, frac, packet.clone())]);
+            let records = read_capture(&format!("classic-{}-{}.pcap", le, nanos), &bytes).unwrap();
+            assert_eq!(records.len(), 1);
+            let record = &records[0];
+            assert_eq!(record.timestamp, expected, "le={} nanos={}", le, nanos);
+            assert_eq!(record.src, Some(IpAddr::from(LOCAL)));
+            assert_eq!(record.dst, Some(IpAddr::from(REMOTE)));
+            assert_eq!((record.proto, record.src_port, record.dst_port), (IPPROTO_UDP, 50000, 443));
+            assert_eq!(record.len, 20 + 8 + 4);
+            assert_eq!((record.src_mac, record.dst_mac), (Some(SRC_MAC), Some(DST_MAC)));
+        }
+    }
+
+    #[test]
+    fn unknown_magic_is_rejected() {
+        assert!(matches!(read_capture("unknown.pcap", b"not a capture file"), Err(Error::Capture(_))));
+    }
+
+    #[test]
+    fn truncated_record_is_dropped() {
+        let packet = ipv4(IPPROTO_TCP, LOCAL, REMOTE, &tcp(50000, 80, &[]));
+        let mut bytes = classic(true, false, LINKTYPE_RAW, &[(1, 0, packet.clone()), (2, 0, packet)]);
+        bytes.truncate(bytes.len() - 10);
+        let records = read_capture("truncated.pcap", &bytes).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].timestamp, 1.0);
+
+        // 记录头本身被截断
+        let mut bytes = classic(true, false, LINKTYPE_RAW, &[(1, 0, ipv4(IPPROTO_TCP, LOCAL, REMOTE, &tcp(50000, 80, &[])))]);
+        bytes.extend_from_slice(&[0; 7]);
+        assert_eq!(read_capture("truncated-header.pcap", &bytes).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn pcapng_blocks_and_timestamp_resolution() {
+        let packet = ipv4(IPPROTO_UDP, LOCAL, REMOTE, &udp(50000, 443, &[]));
+        let frame = ethernet(&[], ETHERTYPE_IPV4, &packet);
+
+        let mut bytes = shb(true);
+        // 接口 0：以太网，默认微秒
+        bytes.extend(idb(true, LINKTYPE_ETHERNET as u16, &[]));
+        // 接口 1：纳秒精度，时间戳另加 100 秒
+        let mut offset = u32_bytes(0, true).to_vec();
+        offset.extend_from_slice(&u32_bytes(100, true));
+        bytes.extend(idb(true, LINKTYPE_RAW as u16, &[(PCAPNG_OPT_IF_TSRESOL, vec![9]), (PCAPNG_OPT_IF_TSOFFSET, offset)]));
+        // 接口 2：2 的负 10 次幂
+        bytes.extend(idb(true, LINKTYPE_RAW as u16, &[(PCAPNG_OPT_IF_TSRESOL, vec![0x80 | 10])]));
+        bytes.extend(epb(true, 0, 1_700_000_000_500_000, &frame));
+        bytes.extend(epb(true, 1, 1_700_000_000_250_000_000, &packet));
+        bytes.extend(epb(true, 2, 5 * 1024 + 512, &packet));
+        // 未声明的接口
+        bytes.extend(epb(true, 7, 0, &packet));
+        bytes.extend(spb(true, &frame));
+        // 新的大端 section 重新编号接口
+        bytes.extend(shb(false));
+        bytes.extend(idb(false, LINKTYPE_RAW as u16, &[]));
+        bytes.extend(epb(false, 0, 3_000_000, &packet));
+        bytes.extend(epb(false, 1, 0, &packet));
+
+        let records = read_capture("blocks.pcapng", &bytes).unwrap();
+        let timestamps: Vec<f64> = records.iter().map(|r| r.timestamp).collect();
+        assert_eq!(records.len(), 5);
+        assert_eq!(timestamps[0], 1_700_000_000.5);
+        assert!((timestamps[1] - 1_700_000_100.25).abs() < 1e-6);
+        assert_eq!(timestamps[2], 5.5);
+        // Simple Packet Block 没有时间戳，按接口 0 的链路层解析
+        assert_eq!(timestamps[3], 0.0);
+        assert_eq!(records[3].src_mac, Some(SRC_MAC));
+        assert_eq!(timestamps[4], 3.0);
+        assert!(records.iter().all(|r| r.dst_port == 443 && r.dst == Some(IpAddr::from(REMOTE))));
+    }
+
+    #[test]
+    fn pcapng_rejects_bad_block_length() {
+        let mut bytes = shb(true);
+        bytes.extend_from_slice(&u32_bytes(PCAPNG_EPB, true));
+        bytes.extend_from_slice(&u32_bytes(8, true));
+        assert!(matches!(read_capture("bad-block.pcapng", &bytes), Err(Error::Capture(_))));
+    }
+
+    #[test]
+    fn link_types() {
+        let packet = ipv4(IPPROTO_TCP, LOCAL, REMOTE, &tcp(50000, 443, &[]));
+        let mut null = vec![2, 0, 0, 0];
+        null.extend_from_slice(&packet);
+        let mut sll = vec![0; 14];
+        sll.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        sll.extend_from_slice(&packet);
+        let mut sll2 = ETHERTYPE_IPV4.to_be_bytes().to_vec();
+        sll2.resize(20, 0);
+        sll2.extend_from_slice(&packet);
+
+        for (linktype, data) in [
+            (LINKTYPE_NULL, null),
+            (LINKTYPE_RAW, packet.clone()),
+            (LINKTYPE_IPV4, packet.clone()),
+            (LINKTYPE_LINUX_SLL, sll),
+            (LINKTYPE_LINUX_SLL2, sll2),
+        ] {
+            let record = decode_packet(linktype, &data).unwrap_or_else(|| panic!("linktype {}", linktype));
+            assert_eq!((record.dst, record.dst_port), (Some(IpAddr::from(REMOTE)), 443), "linktype {}", linktype);
+            assert_eq!(record.src_mac, None);
+        }
+        assert!(decode_packet(999, &packet).is_none());
+    }
+
+    #[test]
+    fn vlan_tags_are_stripped() {
+        let packet = ipv4(IPPROTO_TCP, LOCAL, REMOTE, &tcp(50000, 443, &[]));
+        for vlans in [&[0x8100][..], &[0x88A8, 0x8100], &[0x9100, 0x8100]] {
+            let record = decode_packet(LINKTYPE_ETHERNET, &ethernet(vlans, ETHERTYPE_IPV4, &packet)).unwrap();
+            assert_eq!((record.src, record.dst_port), (Some(IpAddr::from(LOCAL)), 443), "{:x?}", vlans);
+            assert_eq!(record.dst_mac, Some(DST_MAC));
+        }
+        // 标签后不是 IP
+        assert!(decode_packet(LINKTYPE_ETHERNET, &ethernet(&[0x8100], 0x0806, &[0; 28])).is_none());
+        // 标签被截断
+        assert!(decode_packet(LINKTYPE_ETHERNET, &ethernet(&[], 0x8100, &[0, 100])).is_none());
+    }
+
+    #[test]
+    fn ipv4_fragments_only_parse_first() {
+        let mut packet = ipv4(IPPROTO_UDP, LOCAL, REMOTE, &udp(50000, 53, &[]));
+        packet[6] = 0x00;
+        packet[7] = 0xB9;
+        let record = decode_packet(LINKTYPE_RAW, &packet).unwrap();
+        assert_eq!((record.proto, record.src_port, record.dst_port), (IPPROTO_UDP, 0, 0));
+    }
+
+    #[test]
+    fn ipv6_extension_headers() {
+        let (src, dst) = ("2001:db8::10", "2606:2800::1");
+        let segment = tcp(50000, 443, &[]);
+
+        // Hop-by-Hop(8 字节) -> Destination Options(16 字节) -> TCP
+        let mut payload = vec![IPV6_EXT_DEST_OPTS, 0, 1, 4, 0, 0, 0, 0];
+        payload.extend_from_slice(&[IPPROTO_TCP, 1]);
+        payload.extend_from_slice(&[0; 14]);
+        payload.extend_from_slice(&segment);
+        let record = decode_packet(LINKTYPE_RAW, &ipv6(IPV6_EXT_HOP_BY_HOP, src, dst, &payload)).unwrap();
+        assert_eq!((record.proto, record.dst_port), (IPPROTO_TCP, 443));
+        assert_eq!(record.len, 40 + payload.len() as u64);
+        assert_eq!(record.dst, Some(dst.parse().unwrap()));
+
+        // AH 的长度以 4 字节为单位：(1 + 2) * 4 = 12 字节
+        let mut payload = vec![IPPROTO_UDP, 1];
+        payload.extend_from_slice(&[0; 10]);
+        payload.extend_from_slice(&udp(50000, 4500, &[]));
+        let record = decode_packet(LINKTYPE_RAW, &ipv6(IPV6_EXT_AUTH, src, dst, &payload)).unwrap();
+        assert_eq!((record.proto, record.dst_port), (IPPROTO_UDP, 4500));
+
+        // 首个分片解析传输层，后续分片只记录协议
+        let mut first = vec![IPPROTO_UDP, 0, 0x00, 0x01, 0, 0, 0, 1];
+        first.extend_from_slice(&udp(50000, 4500, &[]));
+        let record = decode_packet(LINKTYPE_RAW, &ipv6(IPV6_EXT_FRAGMENT, src, dst, &first)).unwrap();
+        assert_eq!((record.proto, record.dst_port), (IPPROTO_UDP, 4500));
+        let later = [IPPROTO_UDP, 0, 0x00, 0xB9, 0, 0, 0, 1, 0xDE, 0xAD];
+        let record = decode_packet(LINKTYPE_RAW, &ipv6(IPV6_EXT_FRAGMENT, src, dst, &later)).unwrap();
+        assert_eq!((record.proto, record.dst_port), (IPPROTO_UDP, 0));
+    }
+
+    #[test]
+    fn ipv6_extension_header_out_of_range() {
+        let (src, dst) = ("2001:db8::10", "2606:2800::1");
+
+        // 扩展头长度超出负载：仍统计该包，但不解析传输层
+        let mut payload = vec![IPPROTO_TCP, 255, 0, 0, 0, 0, 0, 0];
+        payload.extend_from_slice(&tcp(50000, 443, &[]));
+        let record = decode_packet(LINKTYPE_RAW, &ipv6(IPV6_EXT_HOP_BY_HOP, src, dst, &payload)).unwrap();
+        assert_eq!((record.proto, record.dst_port), (0, 0));
+        assert_eq!(record.len, 40 + payload.len() as u64);
+
+        // 声明了扩展头但负载为空（如抓包长度截断）
+        let record = decode_packet(LINKTYPE_RAW, &ipv6(IPV6_EXT_ROUTING, src, dst, &[])).unwrap();
+        assert_eq!((record.proto, record.dst_port), (0, 0));
+        let record = decode_packet(LINKTYPE_RAW, &ipv6(IPV6_EXT_FRAGMENT, src, dst, &[IPPROTO_UDP, 0])).unwrap();
+        assert_eq!((record.proto, record.dst_port), (IPPROTO_UDP, 0));
+
+        // 负载长度大于实际数据
+        let mut packet = ipv6(IPPROTO_TCP, src, dst, &tcp(50000, 443, &[]));
+        packet[4..6].copy_from_slice(&1000u16.to_be_bytes());
+        let record = decode_packet(LINKTYPE_RAW, &packet).unwrap();
+        assert_eq!((record.len, record.dst_port), (1040, 443));
+    }
+
+    #[test]
+    fn dns_query_name() {
+        let mut msg = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        msg.extend(dns_name("www.Example.com"));
+        msg.extend_from_slice(&[0, 1, 0, 1]);
+
+        let record = decode_ipv4(IPPROTO_UDP, &udp(50000, 53, &msg));
+        assert_eq!(record.dns_name, "www.Example.com");
+        // 查询没有应答记录
+        assert!(record.dns_answers.is_empty());
+
+        // mDNS 与 TCP 上带 2 字节长度前缀的 DNS
+        assert_eq!(decode_ipv4(IPPROTO_UDP, &udp(5353, 5353, &msg)).dns_name, "www.Example.com");
+        let mut framed = (msg.len() as u16).to_be_bytes().to_vec();
+        framed.extend_from_slice(&msg);
+        assert_eq!(decode_ipv4(IPPROTO_TCP, &tcp(50000, 53, &framed)).dns_name, "www.Example.com");
+
+        // 其他端口不按 DNS 解析
+        assert_eq!(decode_ipv4(IPPROTO_UDP, &udp(50000, 5000, &msg)).dns_name, "");
+    }
+
+    #[test]
+    fn dns_compression_pointer_loop() {
+        // 查询名是指向自身的压缩指针
+        let mut msg = vec![0x12, 0x34, 0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0, 0xC0, 12, 0, 1, 0, 1];
+        msg.extend_from_slice(&[0xC0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 1, 2, 3, 4]);
+        assert!(read_dns_name(&msg, 12).is_none());
+        let record = decode_ipv4(IPPROTO_UDP, &udp(53, 50000, &msg));
+        assert_eq!(record.dns_name, "");
+        assert!(record.dns_answers.is_empty());
+
+        // 两个指针互相引用
+        let mut msg = vec![0x12, 0x34, 0x81, 0x80, 0, 1, 0, 0, 0, 0, 0, 0];
+        msg.extend_from_slice(&[3, b'w', b'w', b'w', 0xC0, 18, 0xC0, 12]);
+        assert!(read_dns_name(&msg, 12).is_none());
+
+        // 正常的压缩指针
+        let mut msg = vec![0x12, 0x34, 0x81, 0x80, 0, 1, 0, 0, 0, 0, 0, 0];
+        msg.extend(dns_name("example.com"));
+        msg.extend_from_slice(&[3, b'w', b'w', b'w', 0xC0, 12]);
+        assert_eq!(read_dns_name(&msg, 25), Some(("www.example.com".to_string(), 31)));
+    }
+
+    #[test]
+    fn tls_sni() {
+        let hello = client_hello("api.example.com");
+        let record = decode_ipv4(IPPROTO_TCP, &tcp(50000, 443, &hello));
+        assert_eq!(record.sni, "api.example.com");
+        assert_eq!(record.http_host, "");
+
+        // 不是 ClientHello
+        let mut server_hello = hello.clone();
+        server_hello[5] = 0x02;
+        assert_eq!(parse_tls_sni(&server_hello), None);
+    }
+
+    #[test]
+    fn tls_sni_split_across_segments() {
+        let hello = client_hello("api.example.com");
+        let name_start = hello.windows(15).position(|w| w == b"api.example.com").unwrap();
+
+        // ClientHello 被拆到多个 TCP 段，首段在 SNI 中间结束：不输出半截域名
+        for cut in [name_start - 5, name_start + 4, hello.len() - 8] {
+            assert_eq!(parse_tls_sni(&hello[..cut]), None, "cut at {}", cut);
+        }
+        // 首段在扩展之前结束
+        assert_eq!(parse_tls_sni(&hello[..60]), None);
+        assert_eq!(parse_tls_sni(&hello[..9]), None);
+        // 后续段以扩展数据开头，不是 TLS 记录
+        assert_eq!(parse_tls_sni(&hello[name_start - 9..]), None);
+    }
+
+    #[test]
+    fn http_host() {
+        let request = b"GET /index.html HTTP/1.1\r\nUser-Agent: test\r\nhost: Example.com:8080\r\n\r\n";
+        let record = decode_ipv4(IPPROTO_TCP, &tcp(50000, 8080, request));
+        assert_eq!(record.http_host, "Example.com:8080");
+
+        // 空行之后的内容不是请求头
+        assert_eq!(parse_http_host(b"POST / HTTP/1.1\r\nA: b\r\n\r\nHost: body.example.com\r\n"), None);
+        assert_eq!(parse_http_host(b"HTTP/1.1 200 OK\r\nHost: example.com\r\n\r\n"), None);
+    }
+
+    #[test]
+    fn dhcp_router() {
+        let mut msg = vec![0u8; 240];
+        msg[0] = 2;
+        msg[236..240].copy_from_slice(&DHCP_MAGIC_COOKIE);
+        msg.extend_from_slice(&[DHCP_OPT_PAD, 1, 4, 255, 255, 255, 0]);
+        msg.extend_from_slice(&[DHCP_OPT_ROUTER, 8, 192, 168, 1, 1, 192, 168, 1, 2, DHCP_OPT_END]);
+
+        let packet = ipv4(IPPROTO_UDP, [192, 168, 1, 1], [255, 255, 255, 255], &udp(67, 68, &msg));
+        let record = decode_packet(LINKTYPE_RAW, &packet).unwrap();
+        assert_eq!(record.dhcp_router, Some(Ipv4Addr::new(192, 168, 1, 1)));
+
+        // 请求（BOOTREQUEST）与被截断的选项
+        let mut request = msg.clone();
+        request[0] = 1;
+        assert_eq!(parse_dhcp_router(&request), None);
+        assert_eq!(parse_dhcp_router(&msg[..msg.len() - 6]), None);
+    }
+
+    // 资源记录：所有者 + 类型 + 类(IN) + TTL + 数据长度 + 数据
+    fn resource_record(owner: &[u8], rtype: u16, rdata: &[u8]) -> Vec<u8> {
+        let mut data = owner.to_vec();
+        data.extend_from_slice(&rtype.to_be_bytes());
+        data.extend_from_slice(&[0, 1, 0, 0, 0, 60]);
+        data.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        data.extend_from_slice(rdata);
+        data
+    }
+
+    #[test]
+    fn dns_answers_only_from_answer_section() {
+        // 回答 3 条：CNAME 链与其 A/AAAA；授权区 NS 与附加区的 A 记录不计入
+        let mut msg = vec![0x12, 0x34, 0x81, 0x80, 0, 1, 0, 3, 0, 1, 0, 1];
+        msg.extend(dns_name("www.example.com"));
+        msg.extend_from_slice(&[0, 1, 0, 1]);
+        let cname_target = msg.len() as u8 + 12;
+        msg.extend(resource_record(&[0xC0, 12], DNS_TYPE_CNAME as u16, &dns_name("cdn.example.net")));
+        msg.extend(resource_record(&[0xC0, cname_target], DNS_TYPE_A as u16, &[1, 2, 3, 4]));
+        let v6: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        msg.extend(resource_record(&[0xC0, cname_target], DNS_TYPE_AAAA as u16, &v6.octets()));
+        let answers_end = msg.len();
+        msg.extend(resource_record(&dns_name("example.net"), 2, &dns_name("ns1.example.net")));
+        msg.extend(resource_record(&[0xC0, 12], DNS_TYPE_A as u16, &[6, 6, 6, 6]));
+
+        let expected = vec![
+            DnsAnswer::Cname("www.example.com".to_string(), "cdn.example.net".to_string()),
+            DnsAnswer::Addr("cdn.example.net".to_string(), IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4))),
+            DnsAnswer::Addr("cdn.example.net".to_string(), IpAddr::V6(v6)),
+        ];
+        let record = decode_ipv4(IPPROTO_UDP, &udp(53, 50000, &msg));
+        assert_eq!(record.dns_name, "www.example.com");
+        assert_eq!(record.dns_answers, expected);
+        assert_eq!(parse_dns_answers(&msg[..answers_end]).unwrap(), expected);
+
+        // 应答被截断时保留已读到的记录
+        assert_eq!(parse_dns_answers(&msg[..answers_end - 4]).unwrap(), expected[..2]);
+        // 其他类型的记录跳过：把 A 记录的类型改为 TXT
+        let mut bad = msg.clone();
+        bad[cname_target as usize + 17 + 3] = 16;
+        assert_eq!(parse_dns_answers(&bad).unwrap().len(), 2);
+        // 查询报文没有回答
+        let mut query = msg.clone();
+        query[2] = 0x01;
+        assert_eq!(parse_dns_answers(&query), None);
+    }
+}
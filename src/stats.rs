@@ -1,6 +1,98 @@
+use crate::dns::{format_chain, ResolutionTable};
+use crate::error::Error;
+use crate::flows::FlowTable;
+use crate::packet::{parse_mac, PacketRecord};
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::collections::HashSet;
-use std::net::Ipv4Addr;
+use std::env;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+// 默认的内网网段：RFC1918、运营商级 NAT(CGNAT)、IPv6 唯一本地地址与链路本地地址
+const DEFAULT_INTERNAL_NETS: [&str; 6] = [
+    "10.0.0.0/8",
+    "172.16.0.0/12",
+    "192.168.0.0/16",
+    "100.64.0.0/10",
+    "fc00::/7",
+    "fe80::/10",
+];
+
+/// 内网与排除地址配置，命令行程序由 INTERNAL_NETS 与 EXCLUDE_IPS 设置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetConfig {
+    /// 视为内网的网段，两端都在内网的流量不计入统计
+    pub internal: Vec<IpNet>,
+    /// 网关等需要排除的地址，任一端命中即不计入统计
+    pub excluded: Vec<IpNet>,
+}
+
+impl Default for NetConfig {
+    fn default() -> Self {
+        NetConfig {
+            internal: DEFAULT_INTERNAL_NETS.iter().map(|n| n.parse().unwrap()).collect(),
+            excluded: Vec::new(),
+        }
+    }
+}
+
+impl NetConfig {
+    /// 读取 INTERNAL_NETS 与 EXCLUDE_IPS，未设置 INTERNAL_NETS 时使用默认内网网段
+    pub fn from_env() -> crate::Result<Self> {
+        let internal = match env::var("INTERNAL_NETS") {
+            Ok(v) if !v.trim().is_empty() => parse_net_list("INTERNAL_NETS", &v)?,
+            _ => NetConfig::default().internal,
+        };
+        let excluded = parse_net_list("EXCLUDE_IPS", &env::var("EXCLUDE_IPS").unwrap_or_default())?;
+        Ok(NetConfig { internal, excluded })
+    }
+
+    /// 是否为内网地址
+    pub fn is_internal(&self, ip: &IpAddr) -> bool {
+        self.internal.iter().any(|net| net.contains(ip))
+    }
+
+    /// 是否为需要排除的地址
+    pub fn is_excluded(&self, ip: &IpAddr) -> bool {
+        self.excluded.iter().any(|net| net.contains(ip))
+    }
+}
+
+// 解析逗号分隔的 IP 或 CIDR 列表
+fn parse_net_list(key: &str, value: &str) -> crate::Result<Vec<IpNet>> {
+    value
+        .split(',')
+        .map(|p| p.trim())
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            p.parse::<IpNet>()
+                .map(|net| net.trunc())
+                .or_else(|_| p.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| Error::Config(format!("{} 中的 {} 不是有效的 IP 或网段", key, p)))
+        })
+        .collect()
+}
+
+/// 按 (IP 协议号, 远端端口) 区分的流量，无端口的协议端口为 0
+pub type PortKey = (u8, u16);
+
+#[derive(Default, Debug, Clone, Copy)]
+pub struct PortStat {
+    pub pkts: u64,
+    pub bytes: u64,
+}
+
+/// 单个时间段内的流量
+#[derive(Default, Debug, Clone, Copy)]
+pub struct TimeBucket {
+    pub up_pkts: u64,
+    pub up_bytes: u64,
+    pub down_pkts: u64,
+    pub down_bytes: u64,
+}
 
 #[derive(Default, Debug,Clone)]
 pub struct FlowStat {
@@ -11,71 +103,332 @@ pub struct FlowStat {
     pub down_pkts: u64,
     pub down_bytes: u64,
     pub domains: HashSet<String>,
+    /// 数据包中直接出现的 HTTP Host 与 TLS SNI 及其出现次数（已转小写、去掉端口）
+    pub hosts: HashMap<String, u64>,
+    /// 由 DNS 应答解析到该 IP 的查询域名
+    pub resolved_names: BTreeSet<String>,
+    /// 解析到该 IP 的 CNAME 链（仅包含经过别名的解析），如 "www.example.com -> example.cdn.net"
+    pub cname_chains: BTreeSet<String>,
+    /// 命中的过滤规则，未指定包含规则时为空
+    pub matched_rule: String,
+    pub ports: HashMap<PortKey, PortStat>,
+    /// 首个、最后一个数据包的时间（Unix 秒），没有时间戳时为 0
+    pub first_seen: f64,
+    pub last_seen: f64,
+    /// 按时间段统计的流量，键为时间段起点（Unix 秒），仅在指定统计间隔时记录
+    pub buckets: BTreeMap<i64, TimeBucket>,
 }
 
-pub fn find_local_ip(lines: &[String]) -> Result<String, String> {
-    let mut ip_counts: HashMap<String, usize> = HashMap::new();
-    let mut lan_ips = HashSet::new();
+impl FlowStat {
+    /// 将另一份同一远端 IP 的统计并入当前统计
+    pub fn merge(&mut self, other: FlowStat) {
+        self.total_pkts += other.total_pkts;
+        self.total_bytes += other.total_bytes;
+        self.up_pkts += other.up_pkts;
+        self.up_bytes += other.up_bytes;
+        self.down_pkts += other.down_pkts;
+        self.down_bytes += other.down_bytes;
+        self.domains.extend(other.domains);
+        for (host, count) in other.hosts {
+            *self.hosts.entry(host).or_default() += count;
+        }
+        self.resolved_names.extend(other.resolved_names);
+        self.cname_chains.extend(other.cname_chains);
+        for (key, port) in other.ports {
+            let entry = self.ports.entry(key).or_default();
+            entry.pkts += port.pkts;
+            entry.bytes += port.bytes;
+        }
+        self.observe_time(other.first_seen);
+        self.observe_time(other.last_seen);
+        for (start, bucket) in other.buckets {
+            let entry = self.buckets.entry(start).or_default();
+            entry.up_pkts += bucket.up_pkts;
+            entry.up_bytes += bucket.up_bytes;
+            entry.down_pkts += bucket.down_pkts;
+            entry.down_bytes += bucket.down_bytes;
+        }
+    }
 
-    for line in lines {
-        let cols: Vec<&str> = line.split('\t').collect();
-        if cols.len() < 3 {
-            continue;
+    fn observe_time(&mut self, ts: f64) {
+        // 没有时间戳的数据包不参与首末时间
+        if ts <= 0.0 {
+            return;
         }
+        if self.first_seen == 0.0 || ts < self.first_seen {
+            self.first_seen = ts;
+        }
+        if ts > self.last_seen {
+            self.last_seen = ts;
+        }
+    }
 
-        for ip_str in &[cols[0], cols[1]] {
-            if let Ok(ipv4) = ip_str.parse::<Ipv4Addr>() {
-                if is_lan_ip(&ipv4) && !is_non_host_ip(&ipv4) && *ip_str != "0.0.0.0" {
-                    lan_ips.insert(ip_str.to_string());
-                    *ip_counts.entry(ip_str.to_string()).or_insert(0) += 1;
-                }
-            }
+    // 按统计间隔记入时间段
+    fn add_bucket(&mut self, ts: f64, interval: u64, len: u64, up: bool) {
+        if ts <= 0.0 {
+            return;
         }
+        let interval = interval as i64;
+        let start = (ts as i64).div_euclid(interval) * interval;
+        let bucket = self.buckets.entry(start).or_default();
+        if up {
+            bucket.up_pkts += 1;
+            bucket.up_bytes += len;
+        } else {
+            bucket.down_pkts += 1;
+            bucket.down_bytes += len;
+        }
+    }
+
+    /// 按数据量从大到小排列的协议端口
+    pub fn sorted_ports(&self) -> Vec<(PortKey, PortStat)> {
+        let mut ports: Vec<(PortKey, PortStat)> = self.ports.iter().map(|(k, v)| (*k, *v)).collect();
+        ports.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes).then(a.0.cmp(&b.0)));
+        ports
+    }
+
+    fn add_port(&mut self, key: PortKey, len: u64) {
+        let entry = self.ports.entry(key).or_default();
+        entry.pkts += 1;
+        entry.bytes += len;
     }
+}
+
+/// 各远端 IP 中最早的首次出现时间与最晚的最后出现时间（Unix 秒），都没有时间戳时为 None
+pub fn capture_period(stats: &HashMap<IpAddr, FlowStat>) -> Option<(f64, f64)> {
+    let first = stats.values().map(|stat| stat.first_seen).filter(|ts| *ts > 0.0).min_by(f64::total_cmp)?;
+    let last = stats.values().map(|stat| stat.last_seen).filter(|ts| *ts > 0.0).max_by(f64::total_cmp)?;
+    Some((first, last))
+}
+
+/// 协议名称，常见协议用缩写，其余显示协议号
+pub fn protocol_name(proto: u8) -> String {
+    match proto {
+        1 => "ICMP".to_string(),
+        6 => "TCP".to_string(),
+        17 => "UDP".to_string(),
+        47 => "GRE".to_string(),
+        50 => "ESP".to_string(),
+        58 => "ICMPv6".to_string(),
+        132 => "SCTP".to_string(),
+        0 => "未知".to_string(),
+        n => format!("IP{}", n),
+    }
+}
+
+/// 协议端口的显示形式，如 TCP/443；无端口的协议只显示协议名
+pub fn format_port_key((proto, port): PortKey) -> String {
+    if port == 0 {
+        protocol_name(proto)
+    } else {
+        format!("{}/{}", protocol_name(proto), port)
+    }
+}
 
-    match lan_ips.len() {
-        0 => Err("❌ 未找到局域网 IP".to_string()),
-        1 => Ok(lan_ips.into_iter().next().unwrap()),
-        _ => {
-            // 过滤只包含局域网 IP 的统计信息
-            let mut sorted_ips: Vec<(String, usize)> = ip_counts
-                .into_iter()
-                .filter(|(ip, _)| lan_ips.contains(ip))
-                .collect();
-
-            // 按出现次数从多到少排序
-            sorted_ips.sort_by(|a, b| b.1.cmp(&a.1));
-            
-            // // debug 输出局域网 IP 及其出现次数
-            // println!("局域网 IP 出现次数 Top 5：");
-            // for (ip, count) in sorted_ips.iter().take(5) {
-            //     println!("  - {:<15} 次数: {}", ip, count);
-            // }
-
-            // 判断最大值是否唯一
-            let top_count = sorted_ips[0].1;
-            let top_ips: Vec<&(String, usize)> = sorted_ips
-                .iter()
-                .filter(|(_, count)| *count == top_count)
-                .collect();
-
-            if top_ips.len() == 1 {
-                Ok(top_ips[0].0.clone())
+/// 本机地址：自动定位时 IPv4 为单个地址，IPv6 为本机所在的 /64 前缀（临时地址会轮换）；
+/// 也可以通过 --local 指定 IP、网段或 MAC 地址
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LocalHost {
+    pub nets: Vec<IpNet>,
+    pub macs: Vec<[u8; 6]>,
+}
+
+impl LocalHost {
+    /// 解析逗号分隔的 IP、CIDR 网段或 MAC 地址列表
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut local = LocalHost::default();
+        for part in spec.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            if let Ok(net) = part.parse::<IpNet>() {
+                local.nets.push(net.trunc());
+            } else if let Ok(ip) = part.parse::<IpAddr>() {
+                local.nets.push(IpNet::from(ip));
+            } else if let Some(mac) = parse_mac(part) {
+                local.macs.push(mac);
             } else {
-                Err("❌ 局域网 IP 不唯一，无法自动选择".to_string())
+                return Err(format!("无法识别的本机地址: {}（支持 IP、CIDR 网段或 MAC 地址）", part));
             }
         }
+        if local.nets.is_empty() && local.macs.is_empty() {
+            return Err("本机地址不能为空".to_string());
+        }
+        Ok(local)
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.nets.iter().any(|net| net.contains(ip))
+    }
+
+    /// 数据包的一端是否属于本机：IP 在本机网段内，或 MAC 为本机 MAC
+    pub fn matches(&self, ip: &IpAddr, mac: Option<[u8; 6]>) -> bool {
+        self.contains(ip) || mac.is_some_and(|mac| self.macs.contains(&mac))
+    }
+}
+
+impl From<IpAddr> for LocalHost {
+    fn from(ip: IpAddr) -> Self {
+        LocalHost { nets: vec![IpNet::from(ip)], macs: Vec::new() }
+    }
+}
+
+impl fmt::Display for LocalHost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts: Vec<String> = self
+            .nets
+            .iter()
+            .map(|net| if net.prefix_len() == net.max_prefix_len() { net.addr().to_string() } else { net.to_string() })
+            .collect();
+        parts.extend(self.macs.iter().map(|mac| {
+            mac.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(":")
+        }));
+        write!(f, "{}", parts.join(", "))
     }
 }
 
-fn is_lan_ip(ip: &Ipv4Addr) -> bool {
-    ip.octets()[0] == 10 ||
-    (ip.octets()[0] == 172 && (16..=31).contains(&ip.octets()[1])) ||
-    (ip.octets()[0] == 192 && ip.octets()[1] == 168)
+/// 多主机模式下判断一端是否为需要单独统计的内网主机：
+/// 指定了 --local 时为其中的地址，否则为局域网地址
+pub fn is_internal_host(ip: &IpAddr, mac: Option<[u8; 6]>, local: Option<&LocalHost>, net: &NetConfig) -> bool {
+    match local {
+        Some(local) => local.matches(ip, mac),
+        None => net.is_internal(ip) && !is_non_host_ip(ip) && !ip.is_unspecified(),
+    }
 }
 
-fn is_non_host_ip(ip: &Ipv4Addr) -> bool {
-    ip.is_loopback() || ip.is_link_local() || ip.is_broadcast() || ip.is_multicast()
+/// 逐包统计局域网 IP 的出现次数，用于定位本机地址。
+/// IPv4 只记录局域网地址；IPv6 按 /64 前缀计数，本机前缀出现在其全部 IPv6 数据包中
+#[derive(Debug)]
+pub struct LocalIpDetector {
+    net: NetConfig,
+    ip_counts: HashMap<Ipv4Addr, usize>,
+    v6_prefix_counts: HashMap<Ipv6Net, usize>,
+    // DHCP 应答中出现的网关，不参与定位
+    gateways: HashSet<Ipv4Addr>,
+}
+
+impl LocalIpDetector {
+    pub fn new(net: &NetConfig) -> Self {
+        LocalIpDetector {
+            net: net.clone(),
+            ip_counts: HashMap::new(),
+            v6_prefix_counts: HashMap::new(),
+            gateways: HashSet::new(),
+        }
+    }
+
+    pub fn observe(&mut self, record: &PacketRecord) {
+        if let Some(router) = record.dhcp_router {
+            self.gateways.insert(router);
+        }
+        for ip in [record.src, record.dst].into_iter().flatten() {
+            match local_candidate(&ip, &self.net) {
+                Some(IpNet::V4(net)) => *self.ip_counts.entry(net.addr()).or_insert(0) += 1,
+                Some(IpNet::V6(prefix)) => *self.v6_prefix_counts.entry(prefix).or_insert(0) += 1,
+                None => {}
+            }
+        }
+    }
+
+    pub fn detect(&self) -> Result<LocalHost, String> {
+        let mut nets = Vec::new();
+        if let Some(ipv4) = self.detect_v4()? {
+            nets.push(IpNet::V4(Ipv4Net::from(ipv4)));
+        }
+        if let Some(prefix) = self.detect_v6() {
+            nets.push(IpNet::V6(prefix));
+        }
+
+        if nets.is_empty() {
+            Err("未找到局域网 IP".to_string())
+        } else {
+            Ok(LocalHost { nets, macs: Vec::new() })
+        }
+    }
+
+    fn detect_v4(&self) -> Result<Option<Ipv4Addr>, String> {
+        let candidates: HashMap<&Ipv4Addr, &usize> = self
+            .ip_counts
+            .iter()
+            .filter(|(ip, _)| !self.gateways.contains(ip))
+            .collect();
+        match candidates.len() {
+            0 => Ok(None),
+            1 => Ok(candidates.keys().next().map(|ip| **ip)),
+            _ => {
+                let mut sorted_ips: Vec<(&Ipv4Addr, &usize)> = candidates.into_iter().collect();
+
+                // 按出现次数从多到少排序
+                sorted_ips.sort_by_key(|(_, count)| std::cmp::Reverse(**count));
+
+                // 判断最大值是否唯一
+                let top_count = *sorted_ips[0].1;
+                let top_ips: Vec<&(&Ipv4Addr, &usize)> = sorted_ips
+                    .iter()
+                    .filter(|(_, count)| **count == top_count)
+                    .collect();
+
+                if top_ips.len() == 1 {
+                    Ok(Some(*top_ips[0].0))
+                } else {
+                    Err("局域网 IP 不唯一，无法自动选择".to_string())
+                }
+            }
+        }
+    }
+
+    fn detect_v6(&self) -> Option<Ipv6Net> {
+        let top_count = *self.v6_prefix_counts.values().max()?;
+        let mut top: Vec<&Ipv6Net> = self
+            .v6_prefix_counts
+            .iter()
+            .filter(|(_, count)| **count == top_count)
+            .map(|(prefix, _)| prefix)
+            .collect();
+
+        // 次数相同时优先唯一本地地址(ULA)前缀，仍无法区分则放弃 IPv6 定位
+        if top.len() > 1 {
+            top.retain(|prefix| self.net.is_internal(&IpAddr::V6(prefix.addr())));
+        }
+        if top.len() == 1 {
+            Some(*top[0])
+        } else {
+            log::warn!("无法确定本机 IPv6 前缀，IPv6 流量将不计入统计");
+            None
+        }
+    }
+}
+
+pub fn find_local_ip(records: &[PacketRecord], net: &NetConfig) -> Result<LocalHost, String> {
+    let mut detector = LocalIpDetector::new(net);
+    for record in records {
+        detector.observe(record);
+    }
+    detector.detect()
+}
+
+// 可能是本机的地址：IPv4 为局域网主机地址，IPv6 为所在的 /64 前缀；
+// 链路本地地址只用于邻居发现等，不参与定位
+fn local_candidate(ip: &IpAddr, net: &NetConfig) -> Option<IpNet> {
+    if net.is_excluded(ip) || is_non_host_ip(ip) || ip.is_unspecified() {
+        return None;
+    }
+    match ip {
+        IpAddr::V4(_) => net.is_internal(ip).then(|| IpNet::from(*ip)),
+        IpAddr::V6(ipv6) => Some(IpNet::V6(prefix64(ipv6))),
+    }
+}
+
+fn prefix64(ip: &Ipv6Addr) -> Ipv6Net {
+    Ipv6Net::new(*ip, 64).unwrap().trunc()
+}
+
+fn is_non_host_ip(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            ip.is_loopback() || ip.is_link_local() || ip.is_broadcast() || ip.is_multicast()
+        }
+        IpAddr::V6(ip) => {
+            ip.is_loopback() || ip.is_multicast() || (ip.segments()[0] & 0xFFC0) == 0xFE80
+        }
+    }
 }
 
 fn insert_domain_field(entry: &mut FlowStat, field: &str) {
@@ -102,110 +455,514 @@ fn insert_domain_field(entry: &mut FlowStat, field: &str) {
     }
 }
 
-pub fn aggregate_with_local_ip(
-    lines: &[String],
-    local_ip: &str,
-    company: Option<&str>,
-) -> (HashMap<String, FlowStat>, u64, u64, u64) {
-    let mut stats: HashMap<String, FlowStat> = HashMap::new();
-
-    let mut all_total_bytes: u64 = 0;
-    let mut all_total_up: u64 = 0;
-    let mut all_total_down: u64 = 0;
-
-    for (line_num, line) in lines.iter().enumerate() {
-        let cols: Vec<&str> = line.split('\t').collect();
-        if cols.len() < 3 {
-            eprintln!("第 {} 行格式错误，跳过: {:?}", line_num + 1, line);
+// 记录 HTTP Host / SNI 的出现次数，用于按域名统计时确定 IP 的归属
+fn count_host_field(entry: &mut FlowStat, field: &str) {
+    for host in field.split(',').map(|h| h.trim()).filter(|h| !h.is_empty()) {
+        // HTTP Host 可能带端口；直接写 IP 地址的不是域名，不计入
+        if host.starts_with('[') {
             continue;
         }
-        let src = cols[0];
-        let dst = cols[1];
+        let host = host.split(':').next().unwrap_or(host).trim_end_matches('.').to_lowercase();
+        if !host.is_empty() && host.parse::<IpAddr>().is_err() {
+            *entry.hosts.entry(host).or_default() += 1;
+        }
+    }
+}
 
-        if src.trim().is_empty() || dst.trim().is_empty() {
-            // eprintln!("第 {} 行空 IP，跳过：src='{}', dst='{}'", line_num + 1, src, dst);
-            continue;
+/// [`Aggregator::finish`] 的结果：(统计表, 总流量, 上行, 下行, DNS 解析表, 会话表)
+pub type AggregatorOutput = (HashMap<IpAddr, FlowStat>, u64, u64, u64, ResolutionTable, Option<FlowTable>);
+
+/// 增量聚合器：逐个接收数据包，按远端 IP 累计流量
+#[derive(Debug)]
+pub struct Aggregator {
+    local: LocalHost,
+    net: NetConfig,
+    // 时间序列的统计间隔（秒），None 表示不按时间段统计
+    interval: Option<u64>,
+    stats: HashMap<IpAddr, FlowStat>,
+    // DNS 应答建立的解析表，结束时用于标注各远端 IP 的域名
+    dns: ResolutionTable,
+    // DHCP 应答中出现的网关，与网关之间的流量不计入统计
+    gateways: HashSet<IpAddr>,
+    // 五元组会话表，只记录计入统计的数据包
+    flows: Option<FlowTable>,
+    all_total_bytes: u64,
+    all_total_up: u64,
+    all_total_down: u64,
+}
+
+impl Aggregator {
+    /// flows 为 true 时同时统计五元组会话表
+    pub fn new(local: &LocalHost, net: &NetConfig, interval: Option<u64>, flows: bool) -> Self {
+        Aggregator {
+            local: local.clone(),
+            net: net.clone(),
+            interval,
+            stats: HashMap::new(),
+            dns: ResolutionTable::default(),
+            gateways: HashSet::new(),
+            flows: flows.then(FlowTable::default),
+            all_total_bytes: 0,
+            all_total_up: 0,
+            all_total_down: 0,
         }
+    }
 
-        let src_ip = match src.parse::<Ipv4Addr>() {
-            Ok(ip) => ip,
-            Err(e) => {
-                eprintln!("第 {} 行无法解析 src [{}]: {}", line_num + 1, src, e);
-                continue;
-            }
-        };
+    pub fn local(&self) -> &LocalHost {
+        &self.local
+    }
 
-        let dst_ip = match dst.parse::<Ipv4Addr>() {
-            Ok(ip) => ip,
-            Err(e) => {
-                eprintln!("第 {} 行无法解析 dst [{}]: {}", line_num + 1, dst, e);
-                continue;
-            }
+    pub fn push(&mut self, record: &PacketRecord) {
+        // DNS 服务器可能是网关或内网地址，解析记录在过滤之前收集
+        self.dns.push(&record.dns_answers);
+        self.count(record);
+    }
+
+    // 按远端 IP 累计流量，不处理 DNS 应答
+    fn count(&mut self, record: &PacketRecord) {
+        // 非 IP 数据包没有地址
+        let (Some(src_ip), Some(dst_ip)) = (record.src, record.dst) else {
+            return;
         };
 
-        // 过滤网关 IP
-        if src_ip.octets()[3] == 1 || dst_ip.octets()[3] == 1 {
-            continue;
+        // 过滤网关和配置中排除的地址
+        if let Some(router) = record.dhcp_router {
+            self.gateways.insert(IpAddr::V4(router));
+        }
+        if self.gateways.contains(&src_ip) || self.gateways.contains(&dst_ip) || self.net.is_excluded(&src_ip) || self.net.is_excluded(&dst_ip) {
+            return;
         }
 
-        if is_lan_ip(&src_ip) && is_lan_ip(&dst_ip) {
-            continue;
+        if self.net.is_internal(&src_ip) && self.net.is_internal(&dst_ip) {
+            return;
         }
-    
+
+        let src_local = self.local.matches(&src_ip, record.src_mac);
+        let dst_local = self.local.matches(&dst_ip, record.dst_mac);
+
+        // 同一 IPv6 前缀（或指定网段）内的主机互访
+        if src_local && dst_local {
+            return;
+        }
+
         // 过滤非主机 IP（环回、广播、多播等）
         if is_non_host_ip(&src_ip) || is_non_host_ip(&dst_ip) {
-            continue;
+            return;
         }
 
-        let len: u64 = cols[2].parse().unwrap_or(0);
-
-        // 提取域名字段（后面可能为空）
-        let dns_name = cols.get(3).unwrap_or(&"").trim();
-        let http_host = cols.get(4).unwrap_or(&"").trim();
-        let ssl_sni = cols.get(5).unwrap_or(&"").trim();
+        let len = record.len;
+        if src_local || dst_local {
+            if let Some(flows) = self.flows.as_mut() {
+                flows.push(record);
+            }
+        }
 
-        if src == local_ip {
-            let entry = stats.entry(dst.to_string()).or_default();
+        if src_local {
+            let entry = self.stats.entry(dst_ip).or_default();
             entry.total_pkts += 1;
             entry.total_bytes += len;
             entry.up_pkts += 1;
             entry.up_bytes += len;
+            entry.add_port((record.proto, record.dst_port), len);
+            entry.observe_time(record.timestamp);
+            if let Some(interval) = self.interval {
+                entry.add_bucket(record.timestamp, interval, len, true);
+            }
 
-            insert_domain_field(entry, dns_name);
-            insert_domain_field(entry, http_host);
-            insert_domain_field(entry, ssl_sni);
+            insert_domain_field(entry, &record.dns_name);
+            insert_domain_field(entry, &record.http_host);
+            insert_domain_field(entry, &record.sni);
+            count_host_field(entry, &record.http_host);
+            count_host_field(entry, &record.sni);
 
             // 累计总流量
-            all_total_bytes += len;
-            all_total_up += len;
-        } else if dst == local_ip {
-            let entry = stats.entry(src.to_string()).or_default();
+            self.all_total_bytes += len;
+            self.all_total_up += len;
+        } else if dst_local {
+            let entry = self.stats.entry(src_ip).or_default();
             entry.total_pkts += 1;
             entry.total_bytes += len;
             entry.down_pkts += 1;
             entry.down_bytes += len;
+            entry.add_port((record.proto, record.src_port), len);
+            entry.observe_time(record.timestamp);
+            if let Some(interval) = self.interval {
+                entry.add_bucket(record.timestamp, interval, len, false);
+            }
 
-            insert_domain_field(entry, dns_name);
-            insert_domain_field(entry, http_host);
-            insert_domain_field(entry, ssl_sni);
+            insert_domain_field(entry, &record.dns_name);
+            insert_domain_field(entry, &record.http_host);
+            insert_domain_field(entry, &record.sni);
+            count_host_field(entry, &record.http_host);
+            count_host_field(entry, &record.sni);
 
             // 累计总流量
-            all_total_bytes += len;
-            all_total_down += len;
+            self.all_total_bytes += len;
+            self.all_total_down += len;
+        }
+    }
+
+    // 并入同一文件中另一部分数据包的统计
+    fn absorb(&mut self, other: Aggregator) {
+        for (ip, stat) in other.stats {
+            self.stats.entry(ip).or_default().merge(stat);
+        }
+        self.all_total_bytes += other.all_total_bytes;
+        self.all_total_up += other.all_total_up;
+        self.all_total_down += other.all_total_down;
+        if let (Some(flows), Some(other)) = (self.flows.as_mut(), other.flows) {
+            flows.merge(other);
+        }
+    }
+
+    /// 结束聚合，返回 (统计表, 总流量, 上行, 下行, DNS 解析表, 会话表)；小流量与过滤规则在查询归属地前后再处理。
+    /// 解析表随结果一起返回，合并多个文件后再用 attribute_names 标注域名
+    pub fn finish(self) -> AggregatorOutput {
+        (self.stats, self.all_total_bytes, self.all_total_up, self.all_total_down, self.dns, self.flows)
+    }
+}
+
+/// 按解析表标注每个 IP 对应的域名与 CNAME 链
+pub fn attribute_names(stats: &mut HashMap<IpAddr, FlowStat>, dns: &ResolutionTable) {
+    for (ip, stat) in stats.iter_mut() {
+        for chain in dns.chains(ip) {
+            stat.resolved_names.insert(chain[0].clone());
+            if chain.len() > 1 {
+                stat.cname_chains.insert(format_chain(&chain));
+            }
+        }
+    }
+}
+
+// 预扫描缓存的数据包数，以及预扫描后按出现次数保留的公网 IPv6 前缀数
+const PRESCAN_PACKETS: usize = 10_000;
+const PRESCAN_V6_PREFIXES: usize = 4;
+
+/// 自动定位本机地址的聚合器：一边统计各局域网地址的出现次数，一边按每个候选本机地址
+/// （IPv4 地址或 IPv6 /64 前缀）分别聚合，读完后取出定位结果对应的统计，只需读一遍文件。
+///
+/// 候选只包括可能是本机的地址：局域网 IPv4 地址、内网 IPv6 前缀、发送过链路本地组播
+/// （邻居发现、DHCPv6 等）的前缀，以及前 `PRESCAN_PACKETS` 个数据包中出现最多的
+/// 至多 `PRESCAN_V6_PREFIXES` 个公网 IPv6 前缀。预扫描期间数据包先缓存，结束后按原顺序补计。
+/// 远端公网前缀不会成为候选，内存随有外部流量的局域网主机数增长，与多主机模式相同
+#[derive(Debug)]
+pub struct AutoAggregator {
+    detector: LocalIpDetector,
+    net: NetConfig,
+    interval: Option<u64>,
+    flows: bool,
+    candidates: HashMap<IpNet, Aggregator>,
+    // 解析表与网关对所有候选共用
+    dns: ResolutionTable,
+    gateways: HashSet<IpAddr>,
+    // 预扫描期间缓存的数据包，预扫描结束后为 None
+    prescan: Option<Vec<PacketRecord>>,
+    // 预扫描选出的公网前缀与发送过链路本地组播的前缀
+    selected: HashSet<Ipv6Net>,
+    on_link: HashSet<Ipv6Net>,
+    // 预扫描结束后出现、未作为候选统计的公网前缀
+    skipped: HashSet<Ipv6Net>,
+}
+
+impl AutoAggregator {
+    /// 参数同 [`Aggregator::new`]
+    pub fn new(net: &NetConfig, interval: Option<u64>, flows: bool) -> Self {
+        AutoAggregator {
+            detector: LocalIpDetector::new(net),
+            net: net.clone(),
+            interval,
+            flows,
+            candidates: HashMap::new(),
+            dns: ResolutionTable::default(),
+            gateways: HashSet::new(),
+            prescan: Some(Vec::new()),
+            selected: HashSet::new(),
+            on_link: HashSet::new(),
+            skipped: HashSet::new(),
+        }
+    }
+
+    pub fn push(&mut self, record: &PacketRecord) {
+        self.detector.observe(record);
+        self.dns.push(&record.dns_answers);
+
+        // 链路本地组播不会被路由转发，发送者与抓包主机在同一链路上
+        if let (Some(src), Some(IpAddr::V6(dst))) = (record.src, record.dst) {
+            if dst.segments()[0] == 0xFF02 {
+                if let Some(IpNet::V6(prefix)) = local_candidate(&src, &self.net) {
+                    self.on_link.insert(prefix);
+                }
+            }
+        }
+
+        match self.prescan.as_mut() {
+            Some(buffer) => {
+                buffer.push(record.clone());
+                if buffer.len() >= PRESCAN_PACKETS {
+                    self.end_prescan();
+                }
+            }
+            None => self.count(record),
         }
     }
 
-    // 过滤掉 total_bytes 小于 1024 的项
-    stats.retain(|_, stat| stat.total_bytes >= 1024);
+    // 按预扫描的出现次数选出公网前缀，再按原顺序补计缓存的数据包
+    fn end_prescan(&mut self) {
+        let Some(buffer) = self.prescan.take() else {
+            return;
+        };
+        let mut public: Vec<(&Ipv6Net, &usize)> = self
+            .detector
+            .v6_prefix_counts
+            .iter()
+            .filter(|(prefix, _)| !self.net.is_internal(&IpAddr::V6(prefix.addr())))
+            .collect();
+        public.sort_by_key(|(prefix, count)| (std::cmp::Reverse(**count), **prefix));
+        // 本机前缀出现在其全部 IPv6 数据包中，次数不到最多者一半的前缀不会是本机
+        let top_count = public.first().map_or(0, |(_, count)| **count);
+        self.selected = public
+            .into_iter()
+            .take_while(|(_, count)| **count * 2 >= top_count)
+            .take(PRESCAN_V6_PREFIXES)
+            .map(|(prefix, _)| *prefix)
+            .collect();
 
-    // 最后再根据公司关键字过滤
-    if let Some(keyword) = company {
-        let keyword_lower = keyword.to_lowercase();
-        stats.retain(|_ip, stat| {
-            stat.domains.iter()
-                .any(|domain| domain.to_lowercase().contains(&keyword_lower))
-        });
+        for record in &buffer {
+            self.count(record);
+        }
     }
-    
-    (stats, all_total_bytes, all_total_up, all_total_down)
-}
\ No newline at end of file
+
+    // 把数据包计入其两端对应的候选
+    fn count(&mut self, record: &PacketRecord) {
+        // 新出现的网关同步给已有的候选，之后新建的候选从 gateways 继承
+        if let Some(router) = record.dhcp_router {
+            let router = IpAddr::V4(router);
+            if self.gateways.insert(router) {
+                for agg in self.candidates.values_mut() {
+                    agg.gateways.insert(router);
+                }
+            }
+        }
+
+        let src = record.src.and_then(|ip| self.candidate(&ip));
+        let dst = record.dst.and_then(|ip| self.candidate(&ip));
+        for candidate in [src, dst.filter(|net| Some(*net) != src)].into_iter().flatten() {
+            let (net, interval, flows, gateways) = (&self.net, self.interval, self.flows, &self.gateways);
+            self.candidates
+                .entry(candidate)
+                .or_insert_with(|| {
+                    let mut agg = Aggregator::new(&LocalHost { nets: vec![candidate], macs: Vec::new() }, net, interval, flows);
+                    agg.gateways = gateways.clone();
+                    agg
+                })
+                .count(record);
+        }
+    }
+
+    // 地址所属的候选；公网 IPv6 前缀需在预扫描中选出或发送过链路本地组播
+    fn candidate(&mut self, ip: &IpAddr) -> Option<IpNet> {
+        match local_candidate(ip, &self.net)? {
+            IpNet::V6(prefix)
+                if !self.net.is_internal(ip) && !self.selected.contains(&prefix) && !self.on_link.contains(&prefix) =>
+            {
+                self.skipped.insert(prefix);
+                None
+            }
+            candidate => Some(candidate),
+        }
+    }
+
+    /// 定位本机地址并返回其统计；无法定位时返回错误信息
+    pub fn finish(mut self) -> Result<Aggregator, String> {
+        self.end_prescan();
+        let local = self.detector.detect()?;
+        let mut combined = Aggregator::new(&local, &self.net, self.interval, self.flows);
+        // IPv4 地址与 IPv6 前缀的统计互不重叠，直接合并
+        for net in &local.nets {
+            if let IpNet::V6(prefix) = net {
+                if self.skipped.contains(prefix) {
+                    log::warn!(
+                        "本机 IPv6 前缀 {} 不在前 {} 个数据包出现最多的前缀中，部分流量未计入统计",
+                        prefix,
+                        PRESCAN_PACKETS
+                    );
+                }
+            }
+            if let Some(agg) = self.candidates.remove(net) {
+                combined.absorb(agg);
+            }
+        }
+        combined.dns = self.dns;
+        Ok(combined)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::DnsAnswer;
+
+    const LOCAL: &str = "192.168.1.10";
+    const REMOTE: &str = "93.184.216.34";
+
+    fn record(src: &str, dst: &str, len: u64, timestamp: f64) -> PacketRecord {
+        PacketRecord {
+            timestamp,
+            src: Some(src.parse().unwrap()),
+            dst: Some(dst.parse().unwrap()),
+            len,
+            proto: 6,
+            src_port: 50000,
+            dst_port: 443,
+            ..Default::default()
+        }
+    }
+
+    fn ip(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    // 本机与远端往返，加上局域网内、多播、网关与排除地址的流量
+    fn sample_records() -> Vec<PacketRecord> {
+        let mut up = record(LOCAL, REMOTE, 100, 1000.0);
+        up.sni = "api.example.com".to_string();
+        let mut down = record(REMOTE, LOCAL, 1500, 1075.0);
+        down.src_port = 443;
+        down.dst_port = 50000;
+        let mut dhcp = record("192.168.1.1", "255.255.255.255", 300, 999.0);
+        dhcp.dhcp_router = Some(Ipv4Addr::new(192, 168, 1, 1));
+        let mut dns = record("8.8.8.8", LOCAL, 90, 999.5);
+        dns.dns_answers = vec![DnsAnswer::Addr("api.example.com".to_string(), ip(REMOTE))];
+        vec![
+            dhcp,
+            dns,
+            up,
+            down,
+            record(LOCAL, "192.168.1.20", 60, 1001.0),
+            record(LOCAL, "224.0.0.251", 60, 1002.0),
+            record(LOCAL, "203.0.113.9", 60, 1003.0),
+        ]
+    }
+
+    fn net_config() -> NetConfig {
+        NetConfig { excluded: vec!["8.8.8.8/32".parse().unwrap()], ..NetConfig::default() }
+    }
+
+    #[test]
+    fn aggregator_counts_remote_traffic() {
+        let mut agg = Aggregator::new(&LocalHost::from(ip(LOCAL)), &net_config(), Some(60), true);
+        for record in sample_records() {
+            agg.push(&record);
+        }
+        let (mut stats, total, up, down, dns, flows) = agg.finish();
+        attribute_names(&mut stats, &dns);
+
+        // 8.8.8.8 被排除，但其 DNS 应答仍用于标注域名
+        let remotes: BTreeSet<IpAddr> = stats.keys().copied().collect();
+        assert_eq!(remotes, BTreeSet::from([ip(REMOTE), ip("203.0.113.9")]));
+        assert_eq!((total, up, down), (1660, 160, 1500));
+
+        let stat = &stats[&ip(REMOTE)];
+        assert_eq!((stat.total_pkts, stat.up_bytes, stat.down_bytes), (2, 100, 1500));
+        assert_eq!(stat.hosts.get("api.example.com"), Some(&1));
+        assert!(stat.resolved_names.contains("api.example.com"));
+        let (port, port_stat) = stat.sorted_ports()[0];
+        assert_eq!((port, port_stat.pkts, port_stat.bytes), ((6, 443), 2, 1600));
+        assert_eq!((stat.first_seen, stat.last_seen), (1000.0, 1075.0));
+        // 1000 与 1075 秒分属 960 与 1020 开始的时间段
+        assert_eq!(stat.buckets.keys().copied().collect::<Vec<_>>(), [960, 1020]);
+        // 往返两个方向属于同一会话
+        assert_eq!(flows.unwrap().sorted().len(), 2);
+    }
+
+    #[test]
+    fn auto_aggregator_matches_explicit_local() {
+        let mut auto = AutoAggregator::new(&net_config(), None, false);
+        let mut explicit = Aggregator::new(&LocalHost::from(ip(LOCAL)), &net_config(), None, false);
+        for record in sample_records() {
+            auto.push(&record);
+            explicit.push(&record);
+        }
+        let auto = auto.finish().unwrap();
+        assert_eq!(auto.local(), &LocalHost::from(ip(LOCAL)));
+
+        let (auto_stats, auto_total, ..) = auto.finish();
+        let (explicit_stats, explicit_total, ..) = explicit.finish();
+        assert_eq!(auto_total, explicit_total);
+        let mut auto_ips: Vec<&IpAddr> = auto_stats.keys().collect();
+        let mut explicit_ips: Vec<&IpAddr> = explicit_stats.keys().collect();
+        auto_ips.sort();
+        explicit_ips.sort();
+        assert_eq!(auto_ips, explicit_ips);
+    }
+
+    #[test]
+    fn auto_aggregator_bounds_ipv6_candidates() {
+        // 本机前缀与大量不同的远端公网前缀通信，远端前缀不应成为候选
+        let peers = PRESCAN_PACKETS + 500;
+        let mut auto = AutoAggregator::new(&NetConfig::default(), None, false);
+        for i in 0..peers {
+            let remote = format!("2606:{:x}:{:x}::1", i / 0x10000, i % 0x10000);
+            auto.push(&record("2001:db8:1:2::10", &remote, 60, i as f64));
+        }
+        // 发送过链路本地组播的前缀即使在预扫描后出现也作为候选
+        auto.push(&record("2001:db8:9::1", "ff02::1:ff00:1", 60, 0.0));
+        auto.push(&record("2001:db8:9::1", "2a00:1450::1", 60, 0.0));
+
+        let mut candidates: Vec<String> = auto.candidates.keys().map(|net| net.to_string()).collect();
+        candidates.sort();
+        assert_eq!(candidates, ["2001:db8:1:2::/64", "2001:db8:9::/64"]);
+        assert!(auto.selected.len() <= PRESCAN_V6_PREFIXES);
+
+        let (stats, total, ..) = auto.finish().unwrap().finish();
+        assert_eq!(stats.len(), peers);
+        assert_eq!(total, 60 * peers as u64);
+    }
+
+    #[test]
+    fn local_ip_detection() {
+        let net = NetConfig::default();
+        // DHCP 应答中的网关不参与定位
+        assert_eq!(find_local_ip(&sample_records(), &net).unwrap(), LocalHost::from(ip(LOCAL)));
+
+        // 两个局域网地址出现次数相同
+        let tie = [record("192.168.1.10", REMOTE, 60, 1.0), record("192.168.1.11", REMOTE, 60, 2.0)];
+        assert!(find_local_ip(&tie, &net).is_err());
+        assert!(find_local_ip(&[record(REMOTE, "8.8.8.8", 60, 1.0)], &net).is_err());
+
+        // INTERNAL_NETS 不含的网段不视为局域网
+        let narrow = NetConfig { internal: vec!["10.0.0.0/8".parse().unwrap()], excluded: Vec::new() };
+        assert!(find_local_ip(&tie, &narrow).is_err());
+        assert!(!is_internal_host(&ip("192.168.1.10"), None, None, &narrow));
+        assert!(is_internal_host(&ip("10.1.2.3"), None, None, &narrow));
+    }
+
+    #[test]
+    fn ipv6_local_prefix() {
+        let net = NetConfig::default();
+        // IPv6 按 /64 前缀定位，本机前缀出现在其全部 IPv6 数据包中
+        let v6 = [record("2001:db8:1:2::abcd", "2606:2800::1", 60, 1.0), record("2a00:1450::1", "2001:db8:1:2::1234", 60, 2.0)];
+        assert_eq!(find_local_ip(&v6, &net).unwrap().to_string(), "2001:db8:1:2::/64");
+
+        let mut records = sample_records();
+        records.extend(v6);
+        assert_eq!(find_local_ip(&records, &net).unwrap().to_string(), format!("{}, 2001:db8:1:2::/64", LOCAL));
+
+        // 两个前缀出现次数相同且都不是唯一本地地址，放弃 IPv6 定位
+        assert!(find_local_ip(&[record("2001:db8:1:2::1", "2606:2800::1", 60, 1.0)], &net).is_err());
+    }
+
+    #[test]
+    fn local_host_parse() {
+        let local = LocalHost::parse("192.168.1.10, 2001:db8::/64,aa-bb-cc-dd-ee-ff").unwrap();
+        assert_eq!(local.to_string(), "192.168.1.10, 2001:db8::/64, aa:bb:cc:dd:ee:ff");
+        assert!(local.matches(&ip("2001:db8::1234"), None));
+        assert!(local.matches(&ip("10.0.0.1"), Some([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF])));
+        assert!(!local.matches(&ip("10.0.0.1"), None));
+        // 网段按前缀截断
+        assert_eq!(LocalHost::parse("10.1.2.3/8").unwrap().to_string(), "10.0.0.0/8");
+
+        assert!(LocalHost::parse("not-an-address").is_err());
+        assert!(LocalHost::parse(" , ").is_err());
+    }
+}
@@ -1,33 +1,300 @@
-use std::process::{Command, Stdio};
-use std::fs::File;
-
-pub fn run_tshark(input_pcap: &str, output_tsv: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let mut child = Command::new("tshark")
-        .arg("-r").arg(input_pcap)
-        .arg("-T").arg("fields")
-        .arg("-e").arg("ip.src")
-        .arg("-e").arg("ip.dst")
-        .arg("-e").arg("ip.len")
-        .arg("-e").arg("dns.qry.name")
-        .arg("-e").arg("http.host")
-        .arg("-e").arg("ssl.handshake.extensions_server_name")
+use crate::error::{Error, Result};
+use crate::packet::{parse_mac, DnsAnswer, PacketRecord};
+use std::process::{ChildStdout, Command, Stdio};
+use std::io::{BufRead, BufReader};
+use std::net::IpAddr;
+
+const IPPROTO_ICMP: u8 = 1;
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+const IPPROTO_ICMPV6: u8 = 58;
+
+// DNS 资源记录类型
+const DNS_TYPE_A: &str = "1";
+const DNS_TYPE_CNAME: &str = "5";
+const DNS_TYPE_AAAA: &str = "28";
+
+// 导出字段，顺序即输出列顺序
+const TSHARK_FIELDS: [&str; 26] = [
+    "ip.src",
+    "ip.dst",
+    "ip.len",
+    "dns.qry.name",
+    "http.host",
+    "ssl.handshake.extensions_server_name",
+    "ipv6.src",
+    "ipv6.dst",
+    "ipv6.plen",
+    "ip.proto",
+    "ipv6.nxt",
+    "tcp.srcport",
+    "tcp.dstport",
+    "udp.srcport",
+    "udp.dstport",
+    "frame.time_epoch",
+    "tcp.flags",
+    "eth.src",
+    "eth.dst",
+    "dhcp.option.router",
+    "dns.count.answers",
+    "dns.resp.name",
+    "dns.resp.type",
+    "dns.a",
+    "dns.aaaa",
+    "dns.cname",
+];
+
+/// 运行 tshark 并逐行解析其输出，每解析出一个数据包就回调一次，不落地临时文件
+pub fn run_tshark(
+    input_pcap: &str,
+    on_packet: &mut dyn FnMut(PacketRecord),
+) -> Result<()> {
+    let mut command = Command::new("tshark");
+    command.arg("-r").arg(input_pcap).arg("-T").arg("fields");
+    for field in TSHARK_FIELDS {
+        command.arg("-e").arg(field);
+    }
+    let mut child = command
         // .arg("-E").arg("header=y")
         .arg("-E").arg("separator=\t")
-        .arg("-E").arg("occurrence=f")
+        .arg("-E").arg("occurrence=a")
+        .arg("-E").arg("aggregator=,")
         .stdout(Stdio::piped())
-        .spawn()?;
-
-    let output_file = File::create(output_tsv)?;
-    let mut writer = std::io::BufWriter::new(output_file);
+        .spawn()
+        .map_err(|e| Error::Tshark(format!("无法运行 tshark: {}", e)))?;
 
-    if let Some(mut stdout) = child.stdout.take() {
-        std::io::copy(&mut stdout, &mut writer)?;
+    // 读取出错时结束 tshark，各种情况下都等待子进程退出，避免遗留僵尸进程
+    let stdout = child.stdout.take().expect("tshark 标准输出已设置为管道");
+    let read = read_output(stdout, on_packet);
+    if read.is_err() {
+        let _ = child.kill();
     }
-
     let status = child.wait()?;
+    read?;
     if !status.success() {
-        return Err(format!("❌ tshark命令执行失败").into());
+        return Err(Error::Tshark("tshark命令执行失败".to_string()));
     }
 
     Ok(())
 }
+
+// 逐行解析 tshark 输出
+fn read_output(stdout: ChildStdout, on_packet: &mut dyn FnMut(PacketRecord)) -> Result<()> {
+    let mut reader = BufReader::new(stdout);
+    let mut buf = Vec::new();
+    let mut line_num = 0;
+
+    loop {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf)? == 0 {
+            break;
+        }
+        line_num += 1;
+
+        // 域名中可能含非 UTF-8 字节，按有损方式解码
+        let line = String::from_utf8_lossy(&buf);
+        if let Some(record) = parse_line(line.trim_end_matches(['\r', '\n']), line_num) {
+            on_packet(record);
+        }
+    }
+    Ok(())
+}
+
+fn parse_line(line: &str, line_num: usize) -> Option<PacketRecord> {
+    let cols: Vec<&str> = line.split('\t').map(|c| c.trim()).collect();
+    if cols.len() < 3 {
+        log::warn!("第 {} 行格式错误，跳过: {:?}", line_num, line);
+        return None;
+    }
+    // 字段出现多次时以逗号分隔（如 ICMP 差错报文内嵌的 IP 头），除 DNS 记录外只取第一个
+    let col = |i: usize| cols.get(i).and_then(|c| c.split(',').next()).unwrap_or("").trim();
+
+    // IPv4 与 IPv6 字段二选一；IPv6 长度补上 40 字节固定头，与 ip.len 口径一致
+    let (src, dst, len) = if !col(0).is_empty() {
+        (col(0), col(1), col(2).parse().unwrap_or(0))
+    } else {
+        (col(6), col(7), col(8).parse::<u64>().map(|plen| plen + 40).unwrap_or(0))
+    };
+
+    // ICMP 差错报文内嵌的原始数据包也会导出端口、DNS 等字段，与内置解析器一致只按外层 ICMP 统计
+    let outer_proto: u8 = if !col(9).is_empty() { col(9) } else { col(10) }.parse().unwrap_or(0);
+    let icmp = matches!(outer_proto, IPPROTO_ICMP | IPPROTO_ICMPV6);
+    let app = |i: usize| if icmp { "" } else { col(i) };
+
+    // ipv6.nxt 可能是扩展头，有 TCP/UDP 端口时以端口所在协议为准
+    let port = |i: usize| col(i).parse::<u16>().unwrap_or(0);
+    let (proto, src_port, dst_port) = if icmp {
+        (outer_proto, 0, 0)
+    } else if !col(11).is_empty() {
+        (IPPROTO_TCP, port(11), port(12))
+    } else if !col(13).is_empty() {
+        (IPPROTO_UDP, port(13), port(14))
+    } else {
+        (outer_proto, 0, 0)
+    };
+
+    // tcp.flags 为十六进制，如 0x0012
+    let tcp_flags = u16::from_str_radix(col(16).trim_start_matches("0x"), 16).unwrap_or(0) as u8;
+
+    Some(PacketRecord {
+        timestamp: col(15).parse().unwrap_or(0.0),
+        src_mac: parse_mac(col(17)),
+        dst_mac: parse_mac(col(18)),
+        src: parse_ip(src, line_num),
+        dst: parse_ip(dst, line_num),
+        len,
+        proto,
+        src_port,
+        dst_port,
+        tcp_flags,
+        dhcp_router: col(19).parse().ok(),
+        dns_name: app(3).to_string(),
+        dns_answers: if icmp { Vec::new() } else { parse_dns_answers(&cols) },
+        http_host: app(4).to_string(),
+        sni: app(5).to_string(),
+    })
+}
+
+/// 按 dns.resp.type 的顺序把回答区记录与 dns.a/dns.aaaa/dns.cname 的取值一一对应
+fn parse_dns_answers(cols: &[&str]) -> Vec<DnsAnswer> {
+    let list = |i: usize| -> Vec<&str> {
+        match cols.get(i) {
+            Some(c) if !c.is_empty() => c.split(',').map(|v| v.trim()).collect(),
+            _ => Vec::new(),
+        }
+    };
+    // dns.resp.* 还包含授权区和附加区的记录，只取回答区
+    let count: usize = cols.get(20).and_then(|c| c.split(',').next()).and_then(|c| c.parse().ok()).unwrap_or(0);
+    let names = list(21);
+    let types = list(22);
+    let mut a = list(23).into_iter();
+    let mut aaaa = list(24).into_iter();
+    let mut cname = list(25).into_iter();
+
+    let mut answers = Vec::new();
+    for (name, rtype) in names.iter().zip(&types).take(count) {
+        let answer = match *rtype {
+            DNS_TYPE_A => a.next().and_then(|v| v.parse().ok()).map(|ip| DnsAnswer::Addr(name.to_string(), ip)),
+            DNS_TYPE_AAAA => aaaa.next().and_then(|v| v.parse().ok()).map(|ip| DnsAnswer::Addr(name.to_string(), ip)),
+            DNS_TYPE_CNAME => cname.next().map(|target| DnsAnswer::Cname(name.to_string(), target.to_string())),
+            _ => None,
+        };
+        answers.extend(answer);
+    }
+    answers
+}
+
+fn parse_ip(field: &str, line_num: usize) -> Option<IpAddr> {
+    if field.is_empty() {
+        return None;
+    }
+    match field.parse() {
+        Ok(ip) => Some(ip),
+        Err(e) => {
+            log::warn!("第 {} 行无法解析 IP [{}]: {}", line_num, field, e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 按字段名构造一行 tshark 输出，未给出的字段为空
+    fn line(fields: &[(&str, &str)]) -> String {
+        TSHARK_FIELDS
+            .iter()
+            .map(|name| fields.iter().find(|(field, _)| field == name).map(|(_, value)| *value).unwrap_or(""))
+            .collect::<Vec<_>>()
+            .join("\t")
+    }
+
+    #[test]
+    fn dns_answers_only_from_answer_section() {
+        // 回答区 3 条，之后是授权区 NS 与附加区 A 记录
+        let text = line(&[
+            ("ip.src", "8.8.8.8"),
+            ("ip.dst", "192.168.1.10"),
+            ("ip.len", "180"),
+            ("ip.proto", "17"),
+            ("udp.srcport", "53"),
+            ("udp.dstport", "50000"),
+            ("dns.qry.name", "www.example.com"),
+            ("dns.count.answers", "3"),
+            ("dns.resp.name", "www.example.com,cdn.example.net,cdn.example.net,example.net,ns1.example.net"),
+            ("dns.resp.type", "5,1,28,2,1"),
+            ("dns.a", "1.2.3.4,9.9.9.9"),
+            ("dns.aaaa", "2001:db8::1"),
+            ("dns.cname", "cdn.example.net"),
+        ]);
+        let record = parse_line(&text, 1).unwrap();
+        assert_eq!(record.dns_name, "www.example.com");
+        assert_eq!(
+            record.dns_answers,
+            vec![
+                DnsAnswer::Cname("www.example.com".to_string(), "cdn.example.net".to_string()),
+                DnsAnswer::Addr("cdn.example.net".to_string(), "1.2.3.4".parse().unwrap()),
+                DnsAnswer::Addr("cdn.example.net".to_string(), "2001:db8::1".parse().unwrap()),
+            ]
+        );
+
+        // 没有回答数时不取任何记录
+        let cols: Vec<&str> = text.split('\t').collect();
+        let mut without_count = cols.clone();
+        without_count[20] = "";
+        assert!(parse_dns_answers(&without_count).is_empty());
+        // 查询报文
+        let query = line(&[("ip.src", "192.168.1.10"), ("ip.dst", "8.8.8.8"), ("dns.qry.name", "example.com"), ("dns.count.answers", "0")]);
+        assert!(parse_line(&query, 2).unwrap().dns_answers.is_empty());
+    }
+
+    #[test]
+    fn multi_occurrence_fields_use_outer_packet() {
+        // ICMP 端口不可达报文内嵌本机发出的 DNS 查询，各字段出现两次
+        let text = line(&[
+            ("ip.src", "203.0.113.1,192.168.1.10"),
+            ("ip.dst", "192.168.1.10,203.0.113.53"),
+            ("ip.len", "84,56"),
+            ("ip.proto", "1,17"),
+            ("udp.srcport", "50000"),
+            ("udp.dstport", "53"),
+            ("dns.qry.name", "example.com"),
+            ("frame.time_epoch", "1700000000.500000000"),
+            ("eth.src", "02:00:00:00:00:01"),
+            ("eth.dst", "02-00-00-00-00-02"),
+        ]);
+        let record = parse_line(&text, 1).unwrap();
+        assert_eq!(record.src, Some("203.0.113.1".parse().unwrap()));
+        assert_eq!(record.dst, Some("192.168.1.10".parse().unwrap()));
+        assert_eq!(record.len, 84);
+        assert_eq!((record.proto, record.src_port, record.dst_port), (IPPROTO_ICMP, 0, 0));
+        assert_eq!(record.dns_name, "");
+        assert_eq!(record.timestamp, 1_700_000_000.5);
+        assert_eq!(record.src_mac, Some([2, 0, 0, 0, 0, 1]));
+        assert_eq!(record.dst_mac, Some([2, 0, 0, 0, 0, 2]));
+    }
+
+    #[test]
+    fn ipv6_tcp_line() {
+        let text = line(&[
+            ("ipv6.src", "2001:db8::10"),
+            ("ipv6.dst", "2606:2800::1"),
+            ("ipv6.plen", "517"),
+            ("ipv6.nxt", "0"),
+            ("tcp.srcport", "50000"),
+            ("tcp.dstport", "443"),
+            ("tcp.flags", "0x0018"),
+            ("ssl.handshake.extensions_server_name", "api.example.com"),
+        ]);
+        let record = parse_line(&text, 1).unwrap();
+        assert_eq!(record.dst, Some("2606:2800::1".parse().unwrap()));
+        // 长度补上 40 字节固定头；ipv6.nxt 为扩展头时按端口判断协议
+        assert_eq!((record.len, record.proto, record.dst_port), (557, IPPROTO_TCP, 443));
+        assert_eq!(record.tcp_flags, 0x18);
+        assert_eq!(record.sni, "api.example.com");
+
+        assert!(parse_line("1.1.1.1\t2.2.2.2", 3).is_none());
+        assert_eq!(parse_line(&line(&[("ip.src", "not-an-ip"), ("ip.dst", "2.2.2.2")]), 4).unwrap().src, None);
+    }
+}
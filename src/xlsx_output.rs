@@ -0,0 +1,234 @@
+use crate::analyze::AggregateResult;
+use crate::csv_output::{sort_stats, ByteFormat, ByteUnit, SortKey};
+use crate::domains;
+use crate::flows::format_timestamp;
+use crate::location::GeoLocation;
+use crate::stats::{self, format_port_key};
+use rust_xlsxwriter::{Format, Workbook, Worksheet, XlsxError};
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// 写出 Excel 工作簿：概览、按 IP、按域名，合并多个文件时另有按文件工作表。
+/// 各表冻结表头并启用筛选，每个数据量写成字节数与按 unit_base 进制换算的可读格式两列
+pub fn write_xlsx(
+    output_xlsx: &str,
+    result: &AggregateResult,
+    // None 表示未查询归属地
+    locations: Option<&HashMap<IpAddr, GeoLocation>>,
+    sort: SortKey,
+    unit_base: u64,
+) -> crate::Result<()> {
+    let mut workbook = Workbook::new();
+    let header = Format::new().set_bold();
+    let size = SizeColumns {
+        format: Format::new().set_num_format("#,##0"),
+        bytes: ByteFormat { unit: ByteUnit::Bytes, base: unit_base, human_column: true },
+    };
+
+    write_summary_sheet(workbook.add_worksheet(), result, &header, &size)?;
+    write_ip_sheet(workbook.add_worksheet(), result, locations, sort, &header, &size)?;
+    write_domain_sheet(workbook.add_worksheet(), result, &header, &size)?;
+    if result.files.len() > 1 {
+        write_file_sheet(workbook.add_worksheet(), result, &header, &size)?;
+    }
+
+    workbook.save(output_xlsx)?;
+    Ok(())
+}
+
+// 概览：总计、本机地址、抓包时间段和文件列表
+fn write_summary_sheet(sheet: &mut Worksheet, result: &AggregateResult, header: &Format, size: &SizeColumns) -> Result<(), XlsxError> {
+    sheet.set_name("概览")?;
+    let (first_seen, last_seen) = stats::capture_period(&result.stats).unwrap_or_default();
+
+    let text = [
+        ("本机地址", result.local.to_string()),
+        ("开始时间", format_timestamp(first_seen)),
+        ("结束时间", format_timestamp(last_seen)),
+    ];
+    let counts = [("远端IP数", result.stats.len()), ("文件数", result.files.len())];
+    let totals = [("总数据量", result.total), ("上行数据量", result.up), ("下行数据量", result.down)];
+
+    let mut row = 0;
+    for (name, value) in text {
+        sheet.write_string_with_format(row, 0, name, header)?;
+        sheet.write_string(row, 1, value)?;
+        row += 1;
+    }
+    for (name, count) in counts {
+        sheet.write_string_with_format(row, 0, name, header)?;
+        sheet.write_number(row, 1, count as f64)?;
+        row += 1;
+    }
+    for (name, bytes) in totals {
+        sheet.write_string_with_format(row, 0, name, header)?;
+        size.write(sheet, row, 1, bytes)?;
+        row += 1;
+    }
+
+    row += 1;
+    sheet.write_string_with_format(row, 0, "文件", header)?;
+    for file in &result.files {
+        sheet.write_string(row, 1, file.path.to_string_lossy())?;
+        row += 1;
+    }
+    sheet.autofit();
+    Ok(())
+}
+
+// 按 IP：按排序依据排列，业务说明合并为一列
+fn write_ip_sheet(
+    sheet: &mut Worksheet,
+    result: &AggregateResult,
+    locations: Option<&HashMap<IpAddr, GeoLocation>>,
+    sort: SortKey,
+    header: &Format,
+    size: &SizeColumns,
+) -> Result<(), XlsxError> {
+    sheet.set_name("按IP")?;
+    let sorted_stats = sort_stats(&result.stats, sort, locations);
+    let with_rule = sorted_stats.iter().any(|(_, stat)| !stat.matched_rule.is_empty());
+
+    let mut columns = vec!["IP".to_string()];
+    columns.extend(size.counter_headers());
+    let text_col = columns.len() as u16;
+    columns.extend(
+        ["主要端口", "首次出现", "最后出现", "归属域名", "业务说明", "解析域名", "CNAME链"].map(String::from),
+    );
+    let location_col = columns.len() as u16;
+    columns.extend(["国家", "省份", "城市", "运营商", "ASN", "ASN组织"].map(String::from));
+    if with_rule {
+        columns.push("匹配规则".to_string());
+    }
+    sheet.write_row_with_format(0, 0, &columns, header)?;
+
+    for (i, (ip, stat)) in sorted_stats.iter().enumerate() {
+        let row = i as u32 + 1;
+        sheet.write_string(row, 0, ip.to_string())?;
+        size.write_counters(sheet, row, 1, [stat.total_pkts, stat.total_bytes, stat.up_pkts, stat.up_bytes, stat.down_pkts, stat.down_bytes])?;
+
+        let mut domain_list: Vec<&str> = stat.domains.iter().map(|d| d.as_str()).collect();
+        domain_list.sort();
+        let text = [
+            stat.sorted_ports().first().map(|(key, _)| format_port_key(*key)).unwrap_or_default(),
+            format_timestamp(stat.first_seen),
+            format_timestamp(stat.last_seen),
+            domains::primary_domain(stat).unwrap_or_default().to_string(),
+            domain_list.join("; "),
+            stat.resolved_names.iter().cloned().collect::<Vec<_>>().join("; "),
+            stat.cname_chains.iter().cloned().collect::<Vec<_>>().join("; "),
+        ];
+        sheet.write_row(row, text_col, text)?;
+
+        // 未查询归属地时国家列填“不可用”，查不到时填“未知”
+        let location = match locations {
+            Some(locations) => locations.get(ip).ok_or("未知"),
+            None => Err("不可用"),
+        };
+        match location {
+            Ok(loc) => {
+                sheet.write_row(row, location_col, [&loc.country, &loc.province, &loc.city, &loc.isp])?;
+                if let Some(asn) = loc.asn {
+                    sheet.write_number(row, location_col + 4, asn)?;
+                }
+                sheet.write_string(row, location_col + 5, &loc.org)?;
+            }
+            Err(reason) => {
+                sheet.write_string(row, location_col, reason)?;
+            }
+        }
+        if with_rule {
+            sheet.write_string(row, location_col + 6, &stat.matched_rule)?;
+        }
+    }
+
+    finish_table(sheet, sorted_stats.len(), columns.len())
+}
+
+// 按域名：每个 IP 只归属一个域名，规则同 _domains.csv
+fn write_domain_sheet(sheet: &mut Worksheet, result: &AggregateResult, header: &Format, size: &SizeColumns) -> Result<(), XlsxError> {
+    sheet.set_name("按域名")?;
+    let mut columns = ["域名", "可注册域名", "IP数", "IP列表"].map(String::from).to_vec();
+    columns.extend(size.counter_headers());
+    sheet.write_row_with_format(0, 0, &columns, header)?;
+
+    let by_domain = domains::aggregate_by_domain(&result.stats, false);
+    for (i, (domain, stat)) in by_domain.iter().enumerate() {
+        let row = i as u32 + 1;
+        let registrable = if domain == domains::UNATTRIBUTED { domain.clone() } else { domains::registrable_domain(domain) };
+        sheet.write_string(row, 0, domain)?;
+        sheet.write_string(row, 1, registrable)?;
+        sheet.write_number(row, 2, stat.ips.len() as f64)?;
+        sheet.write_string(row, 3, stat.ips.iter().map(|ip| ip.to_string()).collect::<Vec<_>>().join("; "))?;
+        size.write_counters(sheet, row, 4, [stat.total_pkts, stat.total_bytes, stat.up_pkts, stat.up_bytes, stat.down_pkts, stat.down_bytes])?;
+    }
+
+    finish_table(sheet, by_domain.len(), columns.len())
+}
+
+// 按文件：合并多个文件时各文件的本机地址与流量
+fn write_file_sheet(sheet: &mut Worksheet, result: &AggregateResult, header: &Format, size: &SizeColumns) -> Result<(), XlsxError> {
+    sheet.set_name("按文件")?;
+    let mut columns = ["文件", "本机地址", "远端IP数"].map(String::from).to_vec();
+    for name in ["总数据量", "上行数据量", "下行数据量"] {
+        columns.extend(size.bytes.headers(name));
+    }
+    columns.extend(["开始时间", "结束时间"].map(String::from));
+    sheet.write_row_with_format(0, 0, &columns, header)?;
+
+    for (i, file) in result.files.iter().enumerate() {
+        let row = i as u32 + 1;
+        sheet.write_string(row, 0, file.path.to_string_lossy())?;
+        sheet.write_string(row, 1, file.local.to_string())?;
+        sheet.write_number(row, 2, file.remote_ips as f64)?;
+        for (i, bytes) in [file.total, file.up, file.down].into_iter().enumerate() {
+            size.write(sheet, row, 3 + 2 * i as u16, bytes)?;
+        }
+        sheet.write_string(row, 9, format_timestamp(file.first_seen))?;
+        sheet.write_string(row, 10, format_timestamp(file.last_seen))?;
+    }
+
+    finish_table(sheet, result.files.len(), columns.len())
+}
+
+// 数据量写成两列：带格式的字节数便于求和排序，其后是按进制换算的可读格式
+struct SizeColumns {
+    format: Format,
+    bytes: ByteFormat,
+}
+
+impl SizeColumns {
+    fn write(&self, sheet: &mut Worksheet, row: u32, col: u16, bytes: u64) -> Result<(), XlsxError> {
+        sheet.write_number_with_format(row, col, bytes as f64, &self.format)?;
+        sheet.write_string(row, col + 1, self.bytes.human(bytes))?;
+        Ok(())
+    }
+
+    // 数据包、数据量交替的六个计数的表头，每个数据量占两列
+    fn counter_headers(&self) -> Vec<String> {
+        let mut headers = Vec::new();
+        for (packets, bytes) in [("总数据包", "总数据量"), ("上行数据包", "上行数据量"), ("下行数据包", "下行数据量")] {
+            headers.push(packets.to_string());
+            headers.extend(self.bytes.headers(bytes));
+        }
+        headers
+    }
+
+    // 依次写入与 counter_headers 对应的计数列
+    fn write_counters(&self, sheet: &mut Worksheet, row: u32, mut col: u16, counters: [u64; 6]) -> Result<(), XlsxError> {
+        for pair in counters.chunks(2) {
+            sheet.write_number(row, col, pair[0] as f64)?;
+            self.write(sheet, row, col + 1, pair[1])?;
+            col += 3;
+        }
+        Ok(())
+    }
+}
+
+// 冻结表头、启用筛选并调整列宽
+fn finish_table(sheet: &mut Worksheet, rows: usize, columns: usize) -> Result<(), XlsxError> {
+    sheet.set_freeze_panes(1, 0)?;
+    sheet.autofilter(0, 0, rows as u32, columns as u16 - 1)?;
+    sheet.autofit();
+    Ok(())
+}